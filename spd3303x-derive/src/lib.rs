@@ -0,0 +1,491 @@
+//! Derive macros for the SCPI traits of the `spd3303x` crate.
+//!
+//! Structs describe their wire format with `#[scpi(format(...))]`, a list of
+//! string literals (mnemonics and separators) and fields, in order of appearance.
+//! Tuple struct fields are referenced by index.
+//! Parts wrapped in `[...]` are optional; they are emitted only if all `Option`
+//! fields inside are `Some`, and decode to `None` if they do not match.
+//!
+//! ```ignore
+//! #[derive(ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+//! #[scpi(format([channel, ":"], quantity, "?"), response = GetLimitResponse)]
+//! pub struct GetLimitRequest {
+//!     pub quantity: LimitQuantity,
+//!     pub channel: Option<Channel>,
+//! }
+//!
+//! #[derive(ScpiSerialize, ScpiDeserialize)]
+//! pub enum State {
+//!     #[scpi(literal = "ON")]
+//!     On,
+//!     #[scpi(literal = "OFF")]
+//!     Off,
+//! }
+//! ```
+//!
+//! Literals are emitted in the `MnemonicStyle` passed to `serialize_styled`.
+//!
+//! Requests without a `response` type are answered by `EmptyResponse`.
+//!
+//! Generated code refers to the crate as `::spd3303x`, `#[scpi(crate = "path")]` overrides this,
+//! e.g. `#[scpi(crate = "crate")]` within the `spd3303x` crate itself.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Fields, GenericArgument, Ident, LitInt, LitStr, Member, PathArguments,
+    Token, Type, bracketed,
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+};
+
+#[proc_macro_derive(ScpiSerialize, attributes(scpi))]
+pub fn derive_scpi_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(ScpiDeserialize, attributes(scpi))]
+pub fn derive_scpi_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(ScpiRequest, attributes(scpi))]
+pub fn derive_scpi_request(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_request(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum Part {
+    Literal(LitStr),
+    Field(Member),
+    Optional(Vec<Part>),
+}
+
+impl Parse for Part {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            Ok(Part::Literal(input.parse()?))
+        } else if input.peek(LitInt) {
+            let index: LitInt = input.parse()?;
+            Ok(Part::Field(Member::Unnamed(syn::Index {
+                index: index.base10_parse()?,
+                span: index.span(),
+            })))
+        } else if input.peek(Ident) {
+            Ok(Part::Field(Member::Named(input.parse()?)))
+        } else if input.peek(syn::token::Bracket) {
+            let content;
+            bracketed!(content in input);
+            let parts = Punctuated::<Part, Token![,]>::parse_terminated(&content)?;
+            Ok(Part::Optional(parts.into_iter().collect()))
+        } else {
+            Err(input.error("expected string literal, field or `[...]`"))
+        }
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttributes {
+    format: Option<Vec<Part>>,
+    response: Option<Type>,
+    krate: Option<syn::Path>,
+}
+
+fn container_attributes(input: &DeriveInput) -> syn::Result<ContainerAttributes> {
+    let mut attributes = ContainerAttributes::default();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("scpi")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("format") {
+                let content;
+                syn::parenthesized!(content in meta.input);
+                let parts = Punctuated::<Part, Token![,]>::parse_terminated(&content)?;
+                attributes.format = Some(parts.into_iter().collect());
+                Ok(())
+            } else if meta.path.is_ident("response") {
+                attributes.response = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("crate") {
+                let path: LitStr = meta.value()?.parse()?;
+                attributes.krate = Some(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta
+                    .error("unsupported scpi attribute, expected `format`, `response` or `crate`"))
+            }
+        })?;
+    }
+    Ok(attributes)
+}
+
+fn variant_literal(variant: &syn::Variant) -> syn::Result<LitStr> {
+    let mut literal = None;
+    for attr in variant.attrs.iter().filter(|a| a.path().is_ident("scpi")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("literal") {
+                literal = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported scpi attribute, expected `literal`"))
+            }
+        })?;
+    }
+    literal.ok_or_else(|| {
+        syn::Error::new(
+            variant.span(),
+            "missing `#[scpi(literal = \"...\")]` on variant",
+        )
+    })
+}
+
+fn require_format(input: &DeriveInput, attributes: ContainerAttributes) -> syn::Result<Vec<Part>> {
+    attributes.format.ok_or_else(|| {
+        syn::Error::new(
+            input.ident.span(),
+            "missing `#[scpi(format(...))]` on struct",
+        )
+    })
+}
+
+fn unit_variants(data: &syn::DataEnum) -> syn::Result<Vec<(&Ident, LitStr)>> {
+    data.variants
+        .iter()
+        .map(|variant| {
+            if !matches!(variant.fields, Fields::Unit) {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "only unit variants are supported",
+                ));
+            }
+            Ok((&variant.ident, variant_literal(variant)?))
+        })
+        .collect()
+}
+
+fn field_type<'a>(fields: &'a Fields, member: &Member) -> syn::Result<&'a Type> {
+    let found = fields
+        .iter()
+        .enumerate()
+        .find(|(index, field)| match member {
+            Member::Named(name) => field.ident.as_ref() == Some(name),
+            Member::Unnamed(unnamed) => unnamed.index as usize == *index,
+        });
+    found
+        .map(|(_, field)| &field.ty)
+        .ok_or_else(|| syn::Error::new(member.span(), "unknown field"))
+}
+
+/// Returns `T` for a type spelled `Option<T>`.
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(arguments) = &segment.arguments else {
+        return None;
+    };
+    match arguments.args.first()? {
+        GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+/// Path of the `spd3303x` crate, `::spd3303x` unless overridden with `#[scpi(crate = "...")]`.
+fn krate(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    Ok(match container_attributes(input)?.krate {
+        Some(path) => quote!(#path),
+        None => quote!(::spd3303x),
+    })
+}
+
+fn local(member: &Member) -> Ident {
+    match member {
+        Member::Named(name) => format_ident!("__field_{}", name),
+        Member::Unnamed(index) => format_ident!("__field_{}", index.index),
+    }
+}
+
+fn fields_in(parts: &[Part], out: &mut Vec<Member>) {
+    for part in parts {
+        match part {
+            Part::Literal(_) => {}
+            Part::Field(member) => out.push(member.clone()),
+            Part::Optional(inner) => fields_in(inner, out),
+        }
+    }
+}
+
+fn expand_serialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = &krate(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let parts = require_format(input, container_attributes(input)?)?;
+            serialize_parts(&parts, &data.fields, krate)?
+        }
+        Data::Enum(data) => {
+            let arms = unit_variants(data)?
+                .into_iter()
+                .map(|(variant, literal)| {
+                    quote!(Self::#variant => #krate::push_mnemonic(out, #literal, style),)
+                });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "unions are not supported",
+            ));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::ScpiSerialize for #name #ty_generics #where_clause {
+            fn serialize(&self, out: &mut String) {
+                self.serialize_styled(out, #krate::MnemonicStyle::Long);
+            }
+
+            fn serialize_styled(&self, out: &mut String, style: #krate::MnemonicStyle) {
+                #body
+            }
+        }
+    })
+}
+
+fn serialize_parts(
+    parts: &[Part],
+    fields: &Fields,
+    krate: &TokenStream2,
+) -> syn::Result<TokenStream2> {
+    let mut tokens = TokenStream2::new();
+    for part in parts {
+        tokens.extend(match part {
+            Part::Literal(literal) => quote!(#krate::push_mnemonic(out, #literal, style);),
+            Part::Field(member) => {
+                field_type(fields, member)?;
+                quote!(#krate::ScpiSerialize::serialize_styled(&self.#member, out, style);)
+            }
+            Part::Optional(inner) => {
+                let condition = optional_condition(inner, fields)?;
+                let inner = serialize_parts(inner, fields, krate)?;
+                quote! {
+                    if #condition {
+                        #inner
+                    }
+                }
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+fn optional_condition(parts: &[Part], fields: &Fields) -> syn::Result<TokenStream2> {
+    let mut members = Vec::new();
+    fields_in(parts, &mut members);
+    let mut checks = Vec::new();
+    for member in &members {
+        if option_inner(field_type(fields, member)?).is_some() {
+            checks.push(quote!(self.#member.is_some()));
+        }
+    }
+    if checks.is_empty() {
+        return Err(syn::Error::new(
+            Span::call_site(),
+            "optional part `[...]` must contain at least one `Option` field",
+        ));
+    }
+    Ok(quote!(#(#checks)&&*))
+}
+
+fn expand_deserialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = &krate(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let parts = require_format(input, container_attributes(input)?)?;
+
+            let mut members = Vec::new();
+            fields_in(&parts, &mut members);
+            for (index, field) in data.fields.iter().enumerate() {
+                let member = match &field.ident {
+                    Some(ident) => Member::Named(ident.clone()),
+                    None => Member::Unnamed(index.into()),
+                };
+                if !members.contains(&member) {
+                    return Err(syn::Error::new(
+                        field.span(),
+                        "field is missing from `#[scpi(format(...))]`",
+                    ));
+                }
+            }
+
+            let steps = deserialize_parts(&parts, &data.fields, false, krate)?;
+            let construct = match &data.fields {
+                Fields::Named(named) => {
+                    let assignments = named.named.iter().map(|field| {
+                        let ident = field.ident.as_ref().expect("named field");
+                        let value = local(&Member::Named(ident.clone()));
+                        quote!(#ident: #value)
+                    });
+                    quote!(Self { #(#assignments),* })
+                }
+                Fields::Unnamed(unnamed) => {
+                    let values = (0..unnamed.unnamed.len())
+                        .map(|index| local(&Member::Unnamed(index.into())));
+                    quote!(Self(#(#values),*))
+                }
+                Fields::Unit => quote!(Self),
+            };
+            quote! {
+                #steps
+                Ok(#construct)
+            }
+        }
+        Data::Enum(data) => {
+            let attempts = unit_variants(data)?.into_iter().map(|(variant, literal)| {
+                quote! {
+                    if let Ok(()) = #krate::match_mnemonic(input, #literal) {
+                        return Ok(Self::#variant);
+                    }
+                }
+            });
+            let name = name.to_string();
+            quote! {
+                #(#attempts)*
+                Err(#krate::Error::ResponseDecoding(format!(
+                    "Unexpected token for {}: `{}`",
+                    #name, input
+                )))
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "unions are not supported",
+            ));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::ScpiDeserialize for #name #ty_generics #where_clause {
+            fn deserialize(input: &mut &str) -> #krate::Result<Self> {
+                #body
+            }
+        }
+    })
+}
+
+/// Generates statements decoding `parts` from `input` into `__field_*` locals.
+/// Inside optional parts (`unwrap_option`), `Option` fields decode their inner type.
+fn deserialize_parts(
+    parts: &[Part],
+    fields: &Fields,
+    unwrap_option: bool,
+    krate: &TokenStream2,
+) -> syn::Result<TokenStream2> {
+    let mut tokens = TokenStream2::new();
+    let mut parts = parts.iter().peekable();
+    while let Some(part) = parts.next() {
+        tokens.extend(match part {
+            Part::Literal(literal) => quote!(#krate::match_mnemonic(input, #literal)?;),
+            Part::Field(member) => {
+                let declared = field_type(fields, member)?;
+                let ty = match option_inner(declared) {
+                    Some(inner) if unwrap_option => inner,
+                    _ => declared,
+                };
+                let value = local(member);
+                // A field followed by a literal is delimited by that literal,
+                // which allows free-form fields such as strings.
+                if let Some(Part::Literal(delimiter)) = parts.peek() {
+                    parts.next();
+                    quote! {
+                        let #value = {
+                            let mut segment = #krate::read_until_mnemonic(input, #delimiter)?;
+                            let value = <#ty as #krate::ScpiDeserialize>::deserialize(&mut segment)?;
+                            #krate::check_empty(&mut segment)?;
+                            value
+                        };
+                    }
+                } else {
+                    quote! {
+                        let #value = <#ty as #krate::ScpiDeserialize>::deserialize(input)?;
+                    }
+                }
+            }
+            Part::Optional(inner) => {
+                let mut members = Vec::new();
+                fields_in(inner, &mut members);
+                let values: Vec<_> = members.iter().map(local).collect();
+                let mut wrapped = Vec::new();
+                let mut missing = Vec::new();
+                for (member, value) in members.iter().zip(&values) {
+                    if option_inner(field_type(fields, member)?).is_some() {
+                        wrapped.push(quote!(Some(#value)));
+                        missing.push(quote!(None));
+                    } else {
+                        return Err(syn::Error::new(
+                            member.span(),
+                            "fields inside optional parts `[...]` must be of type `Option`",
+                        ));
+                    }
+                }
+                let steps = deserialize_parts(inner, fields, true, krate)?;
+                quote! {
+                    let (#(#values,)*) = {
+                        let checkpoint = *input;
+                        let optional = |input: &mut &str| -> #krate::Result<_> {
+                            #steps
+                            Ok((#(#wrapped,)*))
+                        };
+                        match optional(input) {
+                            Ok(values) => values,
+                            Err(_) => {
+                                *input = checkpoint;
+                                (#(#missing,)*)
+                            }
+                        }
+                    };
+                }
+            }
+        });
+    }
+    Ok(tokens)
+}
+
+fn expand_request(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let krate = krate(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let response = match container_attributes(input)?.response {
+        Some(response) => quote!(#response),
+        None => quote!(#krate::EmptyResponse),
+    };
+
+    Ok(quote! {
+        impl #impl_generics #krate::ScpiRequest for #name #ty_generics #where_clause {
+            type Response = #response;
+        }
+    })
+}
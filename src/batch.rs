@@ -0,0 +1,194 @@
+//! Several requests executed in one round-trip, see [`Spd3303x::execute_batch`].
+//!
+//! [`Spd3303x::execute_batch`]: crate::spd3303x::Spd3303x::execute_batch
+
+use crate::{
+    Error, MnemonicStyle, Result, ScpiDeserialize, ScpiRequest, check_empty, safety::Setpoints,
+};
+
+/// Separates chained requests within one line.
+pub const SEPARATOR: char = ';';
+
+/// Whether [`Batch`]es are sent as one chained line or as sequential requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChainingMode {
+    /// Probes with chained queries before the first batch, then chains or sends sequentially.
+    #[default]
+    Auto,
+    Chained,
+    Sequential,
+}
+
+/// A sequence of requests, implemented for tuples of requests and for `Vec`s of one request type.
+pub trait Batch {
+    type Responses;
+
+    /// Serializes each request on its own, paired with whether the device answers it.
+    fn serialize_each(&self, style: MnemonicStyle) -> Vec<(String, bool)>;
+
+    /// Setpoints written by each request, in order, see [`ScpiRequest::get_setpoints`].
+    fn setpoints_each(&self) -> Vec<Setpoints>;
+
+    /// Decodes the response lines, one line per answered request, in order.
+    fn deserialize_each(lines: &mut impl Iterator<Item = String>) -> Result<Self::Responses>;
+}
+
+fn serialize_one<Request: ScpiRequest>(request: &Request, style: MnemonicStyle) -> (String, bool) {
+    let mut out = String::with_capacity(32);
+    request.serialize_styled(&mut out, style);
+    (out, !Request::Response::EMPTY)
+}
+
+fn deserialize_one<Response: ScpiDeserialize>(
+    lines: &mut impl Iterator<Item = String>,
+) -> Result<Response> {
+    if Response::EMPTY {
+        return Response::deserialize(&mut "");
+    }
+
+    let line = lines.next().ok_or(Error::ResponseDecoding(
+        "Batch received fewer responses than requested".to_string(),
+    ))?;
+    let mut data = line.as_str();
+    let response = Response::deserialize(&mut data)?;
+    check_empty(&mut data)?;
+    Ok(response)
+}
+
+macro_rules! impl_batch {
+    ($($request:ident),+) => {
+        impl<$($request: ScpiRequest),+> Batch for ($($request,)+) {
+            type Responses = ($($request::Response,)+);
+
+            fn serialize_each(&self, style: MnemonicStyle) -> Vec<(String, bool)> {
+                #[allow(non_snake_case)]
+                let ($($request,)+) = self;
+                vec![$(serialize_one($request, style)),+]
+            }
+
+            fn setpoints_each(&self) -> Vec<Setpoints> {
+                #[allow(non_snake_case)]
+                let ($($request,)+) = self;
+                vec![$($request.get_setpoints()),+]
+            }
+
+            fn deserialize_each(
+                lines: &mut impl Iterator<Item = String>,
+            ) -> Result<Self::Responses> {
+                Ok(($(deserialize_one::<$request::Response>(lines)?,)+))
+            }
+        }
+    };
+}
+
+impl_batch!(A);
+impl_batch!(A, B);
+impl_batch!(A, B, C);
+impl_batch!(A, B, C, D);
+impl_batch!(A, B, C, D, E);
+impl_batch!(A, B, C, D, E, F);
+impl_batch!(A, B, C, D, E, F, G);
+impl_batch!(A, B, C, D, E, F, G, H);
+impl_batch!(A, B, C, D, E, F, G, H, I);
+impl_batch!(A, B, C, D, E, F, G, H, I, J);
+impl_batch!(A, B, C, D, E, F, G, H, I, J, K);
+impl_batch!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+impl<Request: ScpiRequest> Batch for Vec<Request> {
+    type Responses = Vec<Request::Response>;
+
+    fn serialize_each(&self, style: MnemonicStyle) -> Vec<(String, bool)> {
+        self.iter()
+            .map(|request| serialize_one(request, style))
+            .collect()
+    }
+
+    fn setpoints_each(&self) -> Vec<Setpoints> {
+        self.iter().map(ScpiRequest::get_setpoints).collect()
+    }
+
+    fn deserialize_each(lines: &mut impl Iterator<Item = String>) -> Result<Self::Responses> {
+        // The number of responses is unknown here, all lines belong to this batch.
+        let mut responses = Vec::new();
+        if Request::Response::EMPTY {
+            return Ok(responses);
+        }
+        for line in lines {
+            responses.push(deserialize_one(&mut std::iter::once(line))?);
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{
+        Channel, LimitQuantity, MeasureRequest, MemorySlot, Quantity, Reading, RecallRequest,
+        SetLimitRequest, SystemStatusRequest,
+    };
+
+    #[test]
+    fn test_tuple_batch() {
+        let batch = (
+            SetLimitRequest {
+                quantity: LimitQuantity::Voltage,
+                value: Reading::from(3.3),
+                channel: Some(Channel::One),
+            },
+            MeasureRequest {
+                quantity: Quantity::Voltage,
+                channel: Some(Channel::One),
+            },
+            SystemStatusRequest,
+        );
+
+        let requests = batch.serialize_each(MnemonicStyle::Short);
+        assert_eq!(
+            requests,
+            vec![
+                ("CH1:VOLT 3.300".to_string(), false),
+                ("MEAS:VOLT? CH1".to_string(), true),
+                ("SYST:STAT?".to_string(), true),
+            ]
+        );
+
+        let mut lines = ["3.299\n", "0x0214\n"].map(String::from).into_iter();
+        let (_, voltage, status) =
+            <(SetLimitRequest, MeasureRequest, SystemStatusRequest)>::deserialize_each(&mut lines)
+                .unwrap();
+        assert_eq!(voltage.0, Reading::from_millis(3299));
+        assert_eq!(status.value, 0x0214);
+
+        let mut lines = ["3.299\n"].map(String::from).into_iter();
+        assert!(<(MeasureRequest, SystemStatusRequest)>::deserialize_each(&mut lines).is_err());
+
+        assert_eq!(
+            batch.setpoints_each(),
+            vec![
+                Setpoints::Values(vec![(
+                    Some(Channel::One),
+                    LimitQuantity::Voltage,
+                    Reading::from(3.3)
+                )]),
+                Setpoints::Unchanged,
+                Setpoints::Unchanged,
+            ]
+        );
+        assert_eq!(
+            vec![RecallRequest {
+                slot: MemorySlot::One
+            }]
+            .setpoints_each(),
+            vec![Setpoints::Unknown]
+        );
+    }
+
+    #[test]
+    fn test_vec_batch() {
+        let mut lines = ["1.000\n", "2.000\n"].map(String::from).into_iter();
+        let responses = Vec::<MeasureRequest>::deserialize_each(&mut lines).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].0, Reading::from_millis(2000));
+    }
+}
@@ -0,0 +1,159 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use spd3303x::{
+    Result,
+    emergency_stop::EmergencyStop,
+    logger::{DataLogger, LogFormat, LoggerConfig},
+    spd3303x::Spd3303x,
+    watchdog::{Watchdog, WatchdogConfig, WatchdogExit, udp_heartbeats},
+};
+use tokio::net::UdpSocket;
+
+/// Command line interface for the Siglent SPD3303X power supply.
+#[derive(Parser)]
+struct Cli {
+    /// Hostname or address of the power supply, e.g. `192.168.1.10:5025`.
+    #[arg(long)]
+    host: String,
+    /// Serial number the device is verified against, recommended.
+    #[arg(long)]
+    serial: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Records measurements and status of both channels until interrupted (Ctrl+C).
+    Log {
+        #[arg(long, value_enum, default_value = "csv")]
+        format: Format,
+        #[arg(long, default_value = ".")]
+        directory: PathBuf,
+        /// Sampling period in seconds.
+        #[arg(long, default_value = "1", value_parser = positive_seconds)]
+        period: Duration,
+        /// Duration in seconds after which a new file is started.
+        #[arg(long, default_value = "86400", value_parser = positive_seconds)]
+        rotation: Duration,
+    },
+    /// Switches all outputs off once heartbeats stop arriving, armed by the first heartbeat.
+    Watchdog {
+        /// Address heartbeat datagrams are received on.
+        #[arg(long, default_value = "127.0.0.1:5026")]
+        listen: SocketAddr,
+        /// Seconds without heartbeat until outputs are switched off.
+        #[arg(long, default_value = "2", value_parser = positive_seconds)]
+        deadline: Duration,
+    },
+    /// Switches all outputs off on this and additional supplies, over dedicated connections.
+    Estop {
+        /// Additional supplies, repeatable. `--serial` only applies to `--host`.
+        #[arg(long)]
+        also: Vec<String>,
+        /// Waits for Ctrl+C before switching off, e.g. as a standby emergency stop console.
+        #[arg(long)]
+        on_interrupt: bool,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Jsonl,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl From<Format> for LogFormat {
+    fn from(value: Format) -> Self {
+        match value {
+            Format::Csv => LogFormat::Csv,
+            Format::Jsonl => LogFormat::JsonLines,
+            #[cfg(feature = "parquet")]
+            Format::Parquet => LogFormat::Parquet,
+        }
+    }
+}
+
+/// Parses a duration in seconds, rejecting values that are not positive and finite.
+fn positive_seconds(value: &str) -> std::result::Result<Duration, String> {
+    let seconds = value
+        .parse::<f64>()
+        .map_err(|error| format!("`{value}` is not a number: {error}"))?;
+    if seconds <= 0.0 {
+        return Err(format!("`{value}` must be greater than zero"));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|error| format!("`{value}`: {error}"))
+}
+
+async fn interrupted() {
+    // If the signal handler cannot be installed, run until killed.
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Log {
+            format,
+            directory,
+            period,
+            rotation,
+        } => {
+            let mut config = LoggerConfig::new(directory, format.into());
+            config.period = period;
+            config.rotation = rotation;
+            config.serial_number = cli.serial;
+            DataLogger::new(cli.host, config).run(interrupted()).await
+        }
+        Command::Watchdog { listen, deadline } => {
+            let mut config = WatchdogConfig::new(deadline);
+            config.serial_number = cli.serial;
+            let socket = UdpSocket::bind(listen).await?;
+            let exit = Watchdog::new(cli.host, config)
+                .run(udp_heartbeats(socket), interrupted())
+                .await?;
+            if exit == WatchdogExit::Tripped {
+                eprintln!("Heartbeats missed, all outputs switched off");
+            }
+            Ok(())
+        }
+        Command::Estop { also, on_interrupt } => {
+            let stop = EmergencyStop::new();
+            // With a serial number, `--host` is verified up front and its connection kept.
+            let _verified = match &cli.serial {
+                Some(serial) => {
+                    let mut spd = Spd3303x::connect_hostname(&cli.host).await?;
+                    spd.verify_serial_number(serial).await?;
+                    stop.register(spd.emergency_stop_handle());
+                    Some(spd)
+                }
+                None => {
+                    stop.register_host(cli.host.clone());
+                    None
+                }
+            };
+            for host in &also {
+                stop.register_host(host.clone());
+            }
+            let hosts = std::iter::once(cli.host).chain(also).collect::<Vec<_>>();
+            if on_interrupt {
+                interrupted().await;
+            }
+
+            let results = stop.trigger().await;
+            for (host, result) in hosts.iter().zip(&results) {
+                if let Err(error) = result {
+                    eprintln!("Failed to switch off `{host}`: {error}");
+                }
+            }
+            results.into_iter().collect()
+        }
+    }
+}
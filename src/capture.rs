@@ -0,0 +1,248 @@
+//! Continuous polling into a ring buffer, saved to a file once a trigger condition fires.
+
+use std::{collections::VecDeque, path::PathBuf, sync::Arc, time::Duration};
+
+use tokio::{
+    sync::Mutex,
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+    Result,
+    commands::{Channel, ChannelMode, State},
+    logger::{LogFormat, LogRecord, create_writer},
+    sampling::check_period,
+    snapshot::{ChannelSnapshot, Snapshot},
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Measured current above the threshold, in ampere.
+    CurrentAbove(f32),
+    /// Transition from constant voltage to constant current mode.
+    ConstantCurrent,
+    /// Output turned off, e.g. by the timer or at the front panel.
+    OutputOff,
+}
+
+impl Trigger {
+    pub fn fired(&self, previous: Option<&ChannelSnapshot>, current: &ChannelSnapshot) -> bool {
+        match self {
+            Trigger::CurrentAbove(threshold) => current.current > *threshold,
+            Trigger::ConstantCurrent => previous.is_some_and(|previous| {
+                previous.mode == ChannelMode::ConstantVoltage
+                    && current.mode == ChannelMode::ConstantCurrent
+            }),
+            Trigger::OutputOff => previous.is_some_and(|previous| {
+                previous.output == State::On && current.output == State::Off
+            }),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Trigger::CurrentAbove(threshold) => format!("current above {threshold} A"),
+            Trigger::ConstantCurrent => "CV to CC transition".to_string(),
+            Trigger::OutputOff => "output off".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    pub trigger: Trigger,
+    pub period: Duration,
+    /// Snapshots kept before the triggering one.
+    pub pre_trigger: usize,
+    /// Snapshots taken after the triggering one.
+    pub post_trigger: usize,
+    pub path: PathBuf,
+    pub format: LogFormat,
+}
+
+impl CaptureConfig {
+    pub fn new(trigger: Trigger, path: impl Into<PathBuf>, format: LogFormat) -> Self {
+        CaptureConfig {
+            trigger,
+            period: Duration::from_millis(100),
+            pre_trigger: 100,
+            post_trigger: 100,
+            path: path.into(),
+            format,
+        }
+    }
+}
+
+/// Snapshots around a trigger, as saved to [`CaptureConfig::path`].
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub channel: Channel,
+    pub trigger: Trigger,
+    /// Index of the triggering snapshot, less than `pre_trigger` if it fired early.
+    pub trigger_index: usize,
+    pub snapshots: Vec<Snapshot>,
+    pub path: PathBuf,
+}
+
+impl Capture {
+    pub fn trigger_snapshot(&self) -> &Snapshot {
+        &self.snapshots[self.trigger_index]
+    }
+}
+
+/// Keeps the last `pre_trigger` entries until triggered, then `post_trigger` more.
+struct Recorder<T> {
+    pre_trigger: usize,
+    post_trigger: usize,
+    buffer: VecDeque<T>,
+    trigger_index: Option<usize>,
+}
+
+impl<T> Recorder<T> {
+    fn new(pre_trigger: usize, post_trigger: usize) -> Self {
+        Recorder {
+            pre_trigger,
+            post_trigger,
+            buffer: VecDeque::with_capacity(pre_trigger + post_trigger + 1),
+            trigger_index: None,
+        }
+    }
+
+    /// Returns whether the capture is complete.
+    fn push(&mut self, entry: T, fired: bool) -> bool {
+        if self.trigger_index.is_none() && fired {
+            self.trigger_index = Some(self.buffer.len());
+        }
+        self.buffer.push_back(entry);
+        if self.trigger_index.is_none() && self.buffer.len() > self.pre_trigger {
+            self.buffer.pop_front();
+        }
+
+        self.trigger_index
+            .is_some_and(|index| self.buffer.len() > index + self.post_trigger)
+    }
+}
+
+/// Polls snapshots of the device every `config.period` until `config.trigger` fires on `channel`,
+/// then saves the snapshots around the trigger to `config.path`.
+///
+/// Both channels are recorded, so the file has the same columns as the [`DataLogger`] output.
+///
+/// [`DataLogger`]: crate::logger::DataLogger
+pub async fn capture(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: &CaptureConfig,
+) -> Result<Capture> {
+    check_period("Capture period", config.period)?;
+    let identity = spd.lock().await.get_identity().await?;
+
+    let mut ticker = interval(config.period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut recorder = Recorder::new(config.pre_trigger, config.post_trigger);
+    let mut previous: Option<ChannelSnapshot> = None;
+    loop {
+        ticker.tick().await;
+        // The connection is locked per snapshot only, other commands interleave fairly.
+        let snapshot = spd.lock().await.snapshot().await?;
+        let current = *snapshot.get(channel);
+        let fired =
+            recorder.trigger_index.is_none() && config.trigger.fired(previous.as_ref(), &current);
+        previous = Some(current);
+
+        if recorder.push(snapshot, fired) {
+            break;
+        }
+    }
+
+    let trigger_index = recorder.trigger_index.unwrap_or_default();
+    let snapshots = Vec::from(recorder.buffer);
+
+    let metadata = [
+        ("model_number", identity.model_number),
+        ("serial_number", identity.serial_number),
+        ("channel", format!("{channel:?}")),
+        ("trigger", config.trigger.describe()),
+        ("trigger_index", trigger_index.to_string()),
+    ];
+    let mut writer = create_writer(&config.path, config.format, &metadata)?;
+    for snapshot in &snapshots {
+        writer.write(&LogRecord::from(snapshot))?;
+    }
+    writer.finish()?;
+
+    Ok(Capture {
+        channel,
+        trigger: config.trigger,
+        trigger_index,
+        snapshots,
+        path: config.path.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::commands::DisplayMode;
+
+    fn channel_snapshot(current: f32, mode: ChannelMode, output: State) -> ChannelSnapshot {
+        ChannelSnapshot {
+            timestamp: SystemTime::now(),
+            channel: Channel::One,
+            voltage_limit: 5.0,
+            current_limit: 1.0,
+            voltage: 5.0,
+            current,
+            power: 5.0 * current,
+            mode,
+            output,
+            timer: State::Off,
+            display: DisplayMode::DigitalDisplay,
+        }
+    }
+
+    #[test]
+    fn test_trigger() {
+        let cv = channel_snapshot(0.5, ChannelMode::ConstantVoltage, State::On);
+        let cc = channel_snapshot(1.0, ChannelMode::ConstantCurrent, State::On);
+        let off = channel_snapshot(0.0, ChannelMode::ConstantVoltage, State::Off);
+
+        assert!(Trigger::CurrentAbove(0.8).fired(None, &cc));
+        assert!(!Trigger::CurrentAbove(0.8).fired(None, &cv));
+
+        assert!(Trigger::ConstantCurrent.fired(Some(&cv), &cc));
+        assert!(!Trigger::ConstantCurrent.fired(Some(&cc), &cc));
+        assert!(!Trigger::ConstantCurrent.fired(None, &cc));
+
+        assert!(Trigger::OutputOff.fired(Some(&cv), &off));
+        assert!(!Trigger::OutputOff.fired(Some(&off), &off));
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut recorder = Recorder::new(3, 2);
+        for entry in 0..10 {
+            assert!(!recorder.push(entry, false));
+        }
+        assert!(!recorder.push(10, true));
+        assert!(!recorder.push(11, false));
+        assert!(recorder.push(12, false));
+
+        assert_eq!(recorder.trigger_index, Some(3));
+        assert_eq!(Vec::from(recorder.buffer), vec![7, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn test_recorder_early_trigger() {
+        let mut recorder = Recorder::new(3, 1);
+        assert!(!recorder.push(0, true));
+        assert!(recorder.push(1, false));
+
+        assert_eq!(recorder.trigger_index, Some(0));
+        assert_eq!(Vec::from(recorder.buffer), vec![0, 1]);
+    }
+}
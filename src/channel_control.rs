@@ -1,15 +1,29 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use futures_util::Stream;
 use tokio::sync::Mutex;
 
 use crate::{
     Result,
+    capture::{self, Capture, CaptureConfig},
     commands::{
         Channel, GetTimingParametersResponse, LimitQuantity, Quantity, Reading, State,
         TimeInterval, TimingGroup,
     },
+    emulation::{EmulationConfig, Emulator, SourceModel},
     fixed_channel_control::FixedChannelControl,
+    iv_curve::{self, IvCurve, SweepConfig},
+    output_guard::OutputGuard,
+    protection::{ProtectionConfig, ProtectionSupervisor},
+    ramp::{self, RampConfig},
+    safety::SafetyLimits,
+    sampling::{self, Sample},
+    settle::{self, Settled},
+    snapshot::ChannelSnapshot,
     spd3303x::Spd3303x,
+    threshold::{self, Threshold, ThresholdConfig},
+    timer_program::{TimerDifference, TimerProgram},
+    waveform::{Playback, Repeat, Waveform},
 };
 
 pub struct ChannelControl {
@@ -22,6 +36,17 @@ impl ChannelControl {
         ChannelControl { spd, channel }
     }
 
+    pub fn get_channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// The connection shared by all channels, e.g. for [`StatusEvents::spawn`].
+    ///
+    /// [`StatusEvents::spawn`]: crate::events::StatusEvents::spawn
+    pub fn get_device(&self) -> Arc<Mutex<Spd3303x>> {
+        self.spd.clone()
+    }
+
     pub async fn measure(&self, quantity: Quantity) -> Result<f32> {
         let mut spd = self.spd.lock().await;
         spd.measure(self.channel, quantity).await
@@ -32,6 +57,46 @@ impl ChannelControl {
         spd.set_limit(self.channel, quantity, value).await
     }
 
+    /// Caps the setpoints of this channel, see [`Spd3303x::set_safety_limits`].
+    pub async fn set_safety_limits(&self, limits: SafetyLimits) {
+        let mut spd = self.spd.lock().await;
+        spd.set_safety_limits(self.channel, limits)
+    }
+
+    pub async fn get_safety_limits(&self) -> SafetyLimits {
+        let spd = self.spd.lock().await;
+        spd.get_safety_limits(self.channel)
+    }
+
+    /// Sets the limit and waits until the measurement is within `tolerance`,
+    /// see [`settle::set_and_settle`].
+    pub async fn set_and_settle(
+        &self,
+        quantity: LimitQuantity,
+        value: Reading,
+        tolerance: f32,
+        timeout: Duration,
+    ) -> Result<Settled> {
+        settle::set_and_settle(
+            self.spd.clone(),
+            self.channel,
+            quantity,
+            value,
+            tolerance,
+            timeout,
+        )
+        .await
+    }
+
+    /// Polls measurements and status until `predicate` holds, see [`settle::wait_until`].
+    pub async fn wait_until(
+        &self,
+        predicate: impl FnMut(&ChannelSnapshot) -> bool,
+        timeout: Duration,
+    ) -> Result<Settled> {
+        settle::wait_until(self.spd.clone(), self.channel, predicate, timeout).await
+    }
+
     pub async fn get_limit(&self, quantity: LimitQuantity) -> Result<f32> {
         let mut spd = self.spd.lock().await;
         spd.get_limit(self.channel, quantity).await
@@ -47,6 +112,26 @@ impl ChannelControl {
         spd.get_output(self.channel).await
     }
 
+    /// Switches the output on until the returned guard is dropped or disabled.
+    pub async fn enable_guarded(&self) -> Result<OutputGuard> {
+        OutputGuard::enable(self.spd.clone(), self.channel).await
+    }
+
+    /// Steps the voltage setpoint from the current one to `voltage` over `duration`,
+    /// aborting on constant current mode, see [`ramp::ramp`].
+    pub async fn ramp_to(&self, voltage: Reading, duration: Duration, steps: u32) -> Result<()> {
+        self.ramp(voltage, &RampConfig::new(duration, steps)).await
+    }
+
+    pub async fn ramp(&self, voltage: Reading, config: &RampConfig) -> Result<()> {
+        ramp::ramp(self.spd.clone(), self.channel, voltage, config).await
+    }
+
+    /// Ramps the voltage setpoint down to zero, then switches the output off.
+    pub async fn ramp_down_and_off(&self, duration: Duration, steps: u32) -> Result<()> {
+        ramp::ramp_down_and_off(self.spd.clone(), self.channel, duration, steps).await
+    }
+
     pub async fn set_waveform_display(&self, state: State) -> Result<()> {
         let mut spd = self.spd.lock().await;
         spd.set_waveform_display(self.channel, state).await
@@ -77,6 +162,92 @@ impl ChannelControl {
         spd.set_timer(self.channel, state).await
     }
 
+    pub async fn upload_timer_program(&self, program: &TimerProgram) -> Result<()> {
+        let mut spd = self.spd.lock().await;
+        spd.upload_timer_program(self.channel, program).await
+    }
+
+    pub async fn download_timer_program(&self) -> Result<TimerProgram> {
+        let mut spd = self.spd.lock().await;
+        spd.download_timer_program(self.channel).await
+    }
+
+    /// Groups in which the device differs from `program`.
+    pub async fn diff_timer_program(&self, program: &TimerProgram) -> Result<Vec<TimerDifference>> {
+        let device = self.download_timer_program().await?;
+        Ok(program.diff(&device))
+    }
+
+    /// Uploads `program` unless already on the device, then starts the timer.
+    /// Stop it with [`ChannelControl::set_timer`].
+    pub async fn start_timer_program(&self, program: &TimerProgram) -> Result<()> {
+        let mut spd = self.spd.lock().await;
+        let device = spd.download_timer_program(self.channel).await?;
+        if !program.diff(&device).is_empty() {
+            spd.upload_timer_program(self.channel, program).await?;
+        }
+        spd.set_timer(self.channel, State::On).await
+    }
+
+    /// Sweeps the voltage setpoint and records measurements, see [`SweepConfig`].
+    pub async fn trace_iv_curve(&self, config: &SweepConfig) -> Result<IvCurve> {
+        iv_curve::trace_iv_curve(self.spd.clone(), self.channel, config).await
+    }
+
+    /// Searches for the voltage at which `predicate` changes, see [`threshold::search`].
+    pub async fn find_threshold(
+        &self,
+        config: &ThresholdConfig,
+        predicate: impl AsyncFnMut(ChannelSnapshot) -> Result<bool>,
+    ) -> Result<Threshold> {
+        threshold::find_threshold(self.spd.clone(), self.channel, config, predicate).await
+    }
+
+    /// Searches for the voltage at which the current drawn crosses `current`, in ampere.
+    pub async fn find_current_threshold(
+        &self,
+        config: &ThresholdConfig,
+        current: f32,
+    ) -> Result<Threshold> {
+        threshold::find_current_threshold(self.spd.clone(), self.channel, config, current).await
+    }
+
+    /// Plays `waveform` from a background task, see [`Playback`].
+    pub fn play(&self, waveform: Waveform, repeat: Repeat) -> Result<Playback> {
+        Playback::spawn(self.spd.clone(), self.channel, waveform, repeat)
+    }
+
+    /// Reads limits, measurements and status of this channel while locking the device once.
+    pub async fn snapshot(&self) -> Result<ChannelSnapshot> {
+        let mut spd = self.spd.lock().await;
+        spd.channel_snapshot(self.channel).await
+    }
+
+    /// Samples `quantities` every `period`, see [`sampling::measurements`].
+    pub fn measurements(
+        &self,
+        period: Duration,
+        quantities: &[Quantity],
+    ) -> impl Stream<Item = Result<Sample>> + use<> {
+        sampling::measurements(self.spd.clone(), self.channel, period, quantities)
+    }
+
+    /// Polls until `config.trigger` fires on this channel, see [`capture::capture`].
+    pub async fn capture(&self, config: &CaptureConfig) -> Result<Capture> {
+        capture::capture(self.spd.clone(), self.channel, config).await
+    }
+
+    /// Adjusts the voltage setpoint from the measured current according to `model`,
+    /// see [`Emulator`].
+    pub fn emulate(&self, model: impl SourceModel, config: EmulationConfig) -> Result<Emulator> {
+        Emulator::spawn(self.spd.clone(), self.channel, model, config)
+    }
+
+    /// Switches the output off when `config` thresholds are exceeded, see [`ProtectionSupervisor`].
+    pub fn protect(&self, config: ProtectionConfig) -> Result<ProtectionSupervisor> {
+        ProtectionSupervisor::spawn(self.spd.clone(), self.channel, config)
+    }
+
     pub fn to_fixed(self) -> FixedChannelControl {
         self.into()
     }
@@ -0,0 +1,182 @@
+//! Decoding of command lines into typed requests, the reverse direction of
+//! [`Spd3303x`](crate::spd3303x::Spd3303x), e.g. for simulators, proxies or protocol analysis.
+//!
+//! Responses are encoded to the device wire format with [`ScpiSerialize`].
+
+use crate::{
+    Error, MnemonicStyle, Result, ScpiDeserialize, ScpiRequest, ScpiSerialize,
+    commands::{
+        GetDhcpRequest, GetGatewayRequest, GetInstrumentRequest, GetIpAddressRequest,
+        GetLimitRequest, GetSubnetMaskRequest, GetTimingParametersRequest, IdentityRequest,
+        MeasureRequest, RecallRequest, SaveRequest, SetDhcpRequest, SetGatewayRequest,
+        SetInstrumentRequest, SetIpAddressRequest, SetLimitRequest, SetOperationModeRequest,
+        SetOutputStateRequest, SetSubnetMaskRequest, SetTimerStateRequest,
+        SetTimingParametersRequest, SystemErrorRequest, SystemStatusRequest, SystemVersionRequest,
+        WaveformDisplayRequest,
+    },
+};
+
+macro_rules! requests {
+    ($($variant:ident($request:ty)),* $(,)?) => {
+        /// Any request of the SPD3303X command set.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Request {
+            $($variant($request),)*
+        }
+
+        /// Consumes the complete input, the first request matching all of it is returned.
+        impl ScpiDeserialize for Request {
+            fn deserialize(input: &mut &str) -> Result<Self> {
+                $(
+                    if let Ok(request) = <$request as ScpiRequest>::parse(input) {
+                        *input = "";
+                        return Ok(Request::$variant(request));
+                    }
+                )*
+                Err(Error::RequestDecoding(format!("Unknown command `{input}`")))
+            }
+        }
+
+        impl ScpiSerialize for Request {
+            fn serialize(&self, out: &mut String) {
+                match self {
+                    $(Request::$variant(request) => request.serialize(out),)*
+                }
+            }
+
+            fn serialize_styled(&self, out: &mut String, style: MnemonicStyle) {
+                match self {
+                    $(Request::$variant(request) => request.serialize_styled(out, style),)*
+                }
+            }
+        }
+
+        $(
+            impl From<$request> for Request {
+                fn from(value: $request) -> Self {
+                    Request::$variant(value)
+                }
+            }
+        )*
+    };
+}
+
+requests! {
+    Identity(IdentityRequest),
+    Save(SaveRequest),
+    Recall(RecallRequest),
+    SetInstrument(SetInstrumentRequest),
+    GetInstrument(GetInstrumentRequest),
+    Measure(MeasureRequest),
+    SetLimit(SetLimitRequest),
+    GetLimit(GetLimitRequest),
+    SetOutputState(SetOutputStateRequest),
+    SetOperationMode(SetOperationModeRequest),
+    WaveformDisplay(WaveformDisplayRequest),
+    SetTimingParameters(SetTimingParametersRequest),
+    GetTimingParameters(GetTimingParametersRequest),
+    SetTimerState(SetTimerStateRequest),
+    SystemError(SystemErrorRequest),
+    SystemVersion(SystemVersionRequest),
+    SystemStatus(SystemStatusRequest),
+    SetIpAddress(SetIpAddressRequest),
+    GetIpAddress(GetIpAddressRequest),
+    SetSubnetMask(SetSubnetMaskRequest),
+    GetSubnetMask(GetSubnetMaskRequest),
+    SetGateway(SetGatewayRequest),
+    GetGateway(GetGatewayRequest),
+    SetDhcp(SetDhcpRequest),
+    GetDhcp(GetDhcpRequest),
+}
+
+impl Request {
+    /// Parses a single command line, e.g. `CH1:VOLT 3.3` or `OUTP CH2,ON`.
+    /// Mnemonics are accepted in long or short form, case-insensitively.
+    pub fn parse(line: &str) -> Result<Self> {
+        Request::deserialize(&mut line.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{
+        Channel, GetLimitResponse, LimitQuantity, MeasureResponse, OutputChannel, Quantity,
+        Reading, State, SystemStatusResponse,
+    };
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(
+            Request::parse("CH1:VOLT 3.3").unwrap(),
+            Request::SetLimit(SetLimitRequest {
+                quantity: LimitQuantity::Voltage,
+                value: Reading::from_millis(3300),
+                channel: Some(Channel::One),
+            })
+        );
+        assert_eq!(
+            Request::parse("OUTP CH2,ON\n").unwrap(),
+            Request::SetOutputState(SetOutputStateRequest {
+                channel: OutputChannel::Two,
+                state: State::On,
+            })
+        );
+        assert_eq!(
+            Request::parse("measure:current? ch1").unwrap(),
+            Request::Measure(MeasureRequest {
+                quantity: Quantity::Current,
+                channel: Some(Channel::One),
+            })
+        );
+        assert_eq!(
+            Request::parse("CURR?").unwrap(),
+            Request::GetLimit(GetLimitRequest {
+                quantity: LimitQuantity::Current,
+                channel: None,
+            })
+        );
+        for line in ["CH1:VOLT  3.3", "ch1:voltage 3.3"] {
+            assert_eq!(
+                Request::parse(line).unwrap(),
+                Request::SetLimit(SetLimitRequest {
+                    quantity: LimitQuantity::Voltage,
+                    value: Reading::from_millis(3300),
+                    channel: Some(Channel::One),
+                })
+            );
+        }
+        assert!(matches!(
+            SetLimitRequest::parse("CH1:VOLT x"),
+            Err(Error::RequestDecoding(_))
+        ));
+        assert!(Request::parse("OUTP CH4,ON").is_err());
+        assert!(Request::parse("VOLT 3.3 CH1").is_err());
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        for line in [
+            "*IDN?",
+            "INSTrument CH2",
+            "MEASure:POWEr? CH2",
+            "TIMEr:SET CH1,2,3.000,0.500,2",
+            "OUTPut:TRACK 1",
+            "IPaddr 10.11.13.214",
+            "DHCP?",
+        ] {
+            let mut out = String::new();
+            Request::parse(line).unwrap().serialize(&mut out);
+            assert_eq!(out, line);
+        }
+    }
+
+    #[test]
+    fn test_serialize_responses() {
+        let mut out = String::new();
+        MeasureResponse(Reading::from_millis(30000)).serialize(&mut out);
+        GetLimitResponse(Reading::from_millis(500)).serialize(&mut out);
+        SystemStatusResponse { value: 0x0224 }.serialize(&mut out);
+        assert_eq!(out, "30.000\n0.500\n0x0224\n");
+    }
+}
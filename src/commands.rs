@@ -1,19 +1,33 @@
 use std::{net::Ipv4Addr, ops::Neg};
 
 use crate::{
-    EmptyResponse, Error, ScpiDeserialize, ScpiSerialize, impl_scpi_request, impl_scpi_serialize,
-    match_literal, read_all, read_until, read_while, scpi_enum,
+    EmptyResponse, Error, ScpiDeserialize, ScpiRequest, ScpiSerialize, match_literal, read_while,
+    safety::Setpoints,
 };
 
 // 1. *IDN?
 // Command format *IDN?
 // Description Query the manufacturer, product type, series No., software version and hardware version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("*IDN?"), response = IdentityResponse, crate = "crate")]
 pub struct IdentityRequest;
-impl_scpi_serialize!(IdentityRequest, ["*IDN?"]);
 
 // Return Info Manufacturer, product type, series No., software version, hardware version
 // Typical Return Siglent Technologies, SPD3303X, SPD00001130025, 1.01.01.01.02,V3.0
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+#[scpi(format(
+    company_name,
+    ",",
+    model_number,
+    ",",
+    serial_number,
+    ",",
+    software_version,
+    ",",
+    hardware_version,
+    "\n"
+))]
 pub struct IdentityResponse {
     pub company_name: String,
     pub model_number: String,
@@ -22,34 +36,24 @@ pub struct IdentityResponse {
     pub hardware_version: String,
 }
 
-impl ScpiDeserialize for IdentityResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        Ok(IdentityResponse {
-            company_name: read_until(input, ',')?.trim().to_string(),
-            model_number: read_until(input, ',')?.trim().to_string(),
-            serial_number: read_until(input, ',')?.trim().to_string(),
-            software_version: read_until(input, ',')?.trim().to_string(),
-            hardware_version: read_until(input, '\n')?.trim().to_string(),
-        })
-    }
-}
-
-impl_scpi_request!(IdentityRequest, IdentityResponse);
-
 // 2. *SAV
 // Command format: *SAV {1|2|3|4|5}
 // Description: Save current state in nonvolatile memory
 // Example: *SAV 1
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum MemorySlot {
-        One => "1",
-        Two => "2",
-        Three => "3",
-        Four => "4",
-        Five => "5",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum MemorySlot {
+    #[scpi(literal = "1")]
+    One,
+    #[scpi(literal = "2")]
+    Two,
+    #[scpi(literal = "3")]
+    Three,
+    #[scpi(literal = "4")]
+    Four,
+    #[scpi(literal = "5")]
+    Five,
 }
 
 impl TryFrom<u8> for MemorySlot {
@@ -67,33 +71,42 @@ impl TryFrom<u8> for MemorySlot {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("*SAV ", slot), crate = "crate")]
 pub struct SaveRequest {
     pub slot: MemorySlot,
 }
-impl_scpi_serialize!(SaveRequest, ["*SAV ", slot]);
-impl_scpi_request!(SaveRequest, EmptyResponse);
 
 // 3. *RCL
 // Command format *RCL {1|2|3|4|5}
 // Description Recall state that had been saved from nonvolatile memory.
 // Example *RCL 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format("*RCL ", slot), crate = "crate")]
 pub struct RecallRequest {
     pub slot: MemorySlot,
 }
-impl_scpi_serialize!(RecallRequest, ["*RCL ", slot]);
-impl_scpi_request!(RecallRequest, EmptyResponse);
+
+impl ScpiRequest for RecallRequest {
+    type Response = EmptyResponse;
+
+    fn get_setpoints(&self) -> Setpoints {
+        Setpoints::Unknown
+    }
+}
 
 // 4. INSTrument
 // Command format INSTrument {CH1|CH2}
 // Description Select the channel that will be operated.
 // Example INSTrument CH1
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum Channel {
-        One => "CH1",
-        Two => "CH2",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum Channel {
+    #[scpi(literal = "CH1")]
+    One,
+    #[scpi(literal = "CH2")]
+    Two,
 }
 
 impl TryFrom<u8> for Channel {
@@ -108,36 +121,26 @@ impl TryFrom<u8> for Channel {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("INSTrument ", channel), crate = "crate")]
 pub struct SetInstrumentRequest {
     pub channel: Channel,
 }
-impl_scpi_serialize!(SetInstrumentRequest, ["INSTrument ", channel]);
-impl_scpi_request!(SetInstrumentRequest, EmptyResponse);
 
 // Command format INSTrument?
 // Description Query the current operating channel
 // Example INSTrument?
 // Typical Return CH1
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("INSTrument?"), response = GetInstrumentResponse, crate = "crate")]
 pub struct GetInstrumentRequest;
-impl_scpi_serialize!(GetInstrumentRequest, ["INSTrument?"]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(channel, "\n"), crate = "crate")]
 pub struct GetInstrumentResponse {
     pub channel: Channel,
 }
 
-impl ScpiDeserialize for GetInstrumentResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        Ok(GetInstrumentResponse {
-            channel: Channel::deserialize(input)?,
-        })
-    }
-}
-
-impl_scpi_request!(GetInstrumentRequest, GetInstrumentResponse);
-
 // 5. MEASure
 // Command format MEASure:CURRent? [{CH1|CH2}]
 // Description Query current value for specified channel, if there is no specified channel,
@@ -155,23 +158,26 @@ impl_scpi_request!(GetInstrumentRequest, GetInstrumentResponse);
 // Example MEASure:POWEr? CH1
 // Typical Return 90.000
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum Quantity {
-        Current => "CURRent",
-        Voltage => "VOLTage",
-        Power => "POWEr",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum Quantity {
+    #[scpi(literal = "CURRent")]
+    Current,
+    #[scpi(literal = "VOLTage")]
+    Voltage,
+    #[scpi(literal = "POWEr")]
+    Power,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("MEASure:", quantity, "?", [" ", channel]), response = MeasureResponse, crate = "crate")]
 pub struct MeasureRequest {
     pub quantity: Quantity,
     pub channel: Option<Channel>,
 }
-impl_scpi_serialize!(MeasureRequest, ["MEASure:", quantity, "? ", channel]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(0, "\n"), crate = "crate")]
 pub struct MeasureResponse(pub Reading);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -180,7 +186,7 @@ pub struct Reading {
 }
 
 impl Reading {
-    pub fn from_millis(millis: u16) -> Reading {
+    pub const fn from_millis(millis: u16) -> Reading {
         Reading { millis }
     }
 
@@ -223,25 +229,29 @@ impl ScpiSerialize for Reading {
     }
 }
 
+/// Accepts the device format `3.300` as well as shorter forms like `3.3` or `25`,
+/// decimal places beyond millis are truncated.
 impl ScpiDeserialize for Reading {
     fn deserialize(input: &mut &str) -> Result<Self, Error> {
         let whole_part = u16::deserialize(input)?;
-        match_literal(input, ".")?;
-        let frac_part = u16::deserialize(input)?;
-        Ok(Reading::from_millis(whole_part * 1000 + frac_part))
-    }
-}
-
-impl ScpiDeserialize for MeasureResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let value = Reading::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(MeasureResponse(value))
+        let mut frac_part = 0;
+        if match_literal(input, ".").is_ok() {
+            let digits = read_while(input, |c: char| c.is_ascii_digit());
+            let digits = &digits[..digits.len().min(3)];
+            frac_part = format!("{digits:0<3}")
+                .parse()
+                .map_err(|_| Error::ResponseDecoding(format!("Number parsing failed: {digits}")))?;
+        }
+        whole_part
+            .checked_mul(1000)
+            .and_then(|millis| millis.checked_add(frac_part))
+            .map(Reading::from_millis)
+            .ok_or_else(|| {
+                Error::ResponseDecoding(format!("Reading {whole_part}.{frac_part:03} out of range"))
+            })
     }
 }
 
-impl_scpi_request!(MeasureRequest, MeasureResponse);
-
 // 6. CURRent
 // Command format [{CH1|CH2}:]CURRent <current>
 // Description Set current value of the selected channel
@@ -259,9 +269,12 @@ impl_scpi_request!(MeasureRequest, MeasureResponse);
 // Example CH1:VOLTage?
 // Typical Return 25.000
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
 pub enum LimitQuantity {
+    #[scpi(literal = "CURRent")]
     Current,
+    #[scpi(literal = "VOLTage")]
     Voltage,
 }
 impl From<&LimitQuantity> for Quantity {
@@ -273,70 +286,45 @@ impl From<&LimitQuantity> for Quantity {
     }
 }
 
-impl ScpiSerialize for LimitQuantity {
-    fn serialize(&self, out: &mut String) {
-        Quantity::from(self).serialize(out);
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format([channel, ":"], quantity, " ", value), crate = "crate")]
 pub struct SetLimitRequest {
     pub quantity: LimitQuantity,
     pub value: Reading,
     pub channel: Option<Channel>,
 }
-impl ScpiSerialize for SetLimitRequest {
-    fn serialize(&self, out: &mut String) {
-        self.channel.serialize(out);
-        if self.channel.is_some() {
-            out.push(':');
-        }
-        self.quantity.serialize(out);
-        out.push(' ');
-        self.value.serialize(out);
+
+impl ScpiRequest for SetLimitRequest {
+    type Response = EmptyResponse;
+
+    fn get_setpoints(&self) -> Setpoints {
+        Setpoints::Values(vec![(self.channel, self.quantity, self.value)])
     }
 }
-impl_scpi_request!(SetLimitRequest, EmptyResponse);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format([channel, ":"], quantity, "?"), response = GetLimitResponse, crate = "crate")]
 pub struct GetLimitRequest {
     pub quantity: LimitQuantity,
     pub channel: Option<Channel>,
 }
-impl ScpiSerialize for GetLimitRequest {
-    fn serialize(&self, out: &mut String) {
-        self.channel.serialize(out);
-        if self.channel.is_some() {
-            out.push(':');
-        }
-        self.quantity.serialize(out);
-        out.push('?');
-    }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(0, "\n"), crate = "crate")]
 pub struct GetLimitResponse(pub Reading);
-impl ScpiDeserialize for GetLimitResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let value = Reading::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(GetLimitResponse(value))
-    }
-}
-
-impl_scpi_request!(GetLimitRequest, GetLimitResponse);
 
 // 8. OUTPut
 // Command format OUTPut {CH1|CH2|CH3},{ON|OFF}
 // Description Turn on/off the specified channel output.
 // Example OUTPut CH1,ON
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum State {
-        On => "ON",
-        Off => "OFF",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum State {
+    #[scpi(literal = "ON")]
+    On,
+    #[scpi(literal = "OFF")]
+    Off,
 }
 
 impl From<bool> for State {
@@ -368,13 +356,15 @@ impl Neg for State {
     }
 }
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum OutputChannel {
-        One => "CH1",
-        Two => "CH2",
-        Three => "CH3",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum OutputChannel {
+    #[scpi(literal = "CH1")]
+    One,
+    #[scpi(literal = "CH2")]
+    Two,
+    #[scpi(literal = "CH3")]
+    Three,
 }
 
 impl TryFrom<OutputChannel> for Channel {
@@ -398,50 +388,45 @@ impl From<Channel> for OutputChannel {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("OUTPut ", channel, ",", state), crate = "crate")]
 pub struct SetOutputStateRequest {
     pub channel: OutputChannel,
     pub state: State,
 }
-impl_scpi_serialize!(SetOutputStateRequest, ["OUTPut ", channel, ",", state]);
-impl_scpi_request!(SetOutputStateRequest, EmptyResponse);
 
 // Command format OUTPut:TRACK {0|1|2}
 // Description Select operation mode. Parameters {0|1|2} mean independent, series and
 // parallel respectively
 // Example OUTPut:TRACK 0
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum OperationMode {
-        Independent => "0",
-        Series => "1",
-        Parallel => "2",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum OperationMode {
+    #[scpi(literal = "0")]
+    Independent,
+    #[scpi(literal = "1")]
+    Series,
+    #[scpi(literal = "2")]
+    Parallel,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("OUTPut:TRACK ", mode), crate = "crate")]
 pub struct SetOperationModeRequest {
     pub mode: OperationMode,
 }
 
-impl_scpi_serialize!(SetOperationModeRequest, ["OUTPut:TRACK ", mode]);
-impl_scpi_request!(SetOperationModeRequest, EmptyResponse);
-
 // Command format OUTPut:WAVE {CH1|CH2},{ON|OFF}
 // Description Turn on/off the Waveform Display function of specified channel
 // Example OUTPut:WAVE CH1,ON
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("OUTPut:WAVE ", channel, ",", state), crate = "crate")]
 pub struct WaveformDisplayRequest {
     pub channel: Channel,
     pub state: State,
 }
-impl_scpi_serialize!(
-    WaveformDisplayRequest,
-    ["OUTPut:WAVE ", channel, ",", state]
-);
-impl_scpi_request!(WaveformDisplayRequest, EmptyResponse);
 
 // 9. TIMEr
 // Command format TIMEr:SET
@@ -450,23 +435,37 @@ impl_scpi_request!(WaveformDisplayRequest, EmptyResponse);
 // voltage, current, time
 // Example TIMEr:SET CH1,2,3,0.5,2
 
-scpi_enum! {
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-    pub enum TimingGroup {
-        One => "1",
-        Two => "2",
-        Three => "3",
-        Four => "4",
-        Five => "5",
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+pub enum TimingGroup {
+    #[scpi(literal = "1")]
+    One,
+    #[scpi(literal = "2")]
+    Two,
+    #[scpi(literal = "3")]
+    Three,
+    #[scpi(literal = "4")]
+    Four,
+    #[scpi(literal = "5")]
+    Five,
 }
 
+/// Duration of a timer step in seconds.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TimeInterval(u16);
 
+impl TimeInterval {
+    pub const MAX: u16 = 10000;
+    pub const ZERO: TimeInterval = TimeInterval(0);
+
+    pub fn get_seconds(&self) -> u16 {
+        self.0
+    }
+}
+
 impl From<u16> for TimeInterval {
     fn from(value: u16) -> Self {
-        if value > 10000 {
+        if value > Self::MAX {
             panic!("Time interval value {value} exceeds accepted range for SPD3303X (max. 10000)");
         }
         TimeInterval(value)
@@ -486,7 +485,20 @@ impl ScpiDeserialize for TimeInterval {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(crate = "crate")]
+#[scpi(format(
+    "TIMEr:SET ",
+    channel,
+    ",",
+    group,
+    ",",
+    voltage,
+    ",",
+    current,
+    ",",
+    time
+))]
 pub struct SetTimingParametersRequest {
     pub channel: Channel,
     pub group: TimingGroup,
@@ -494,120 +506,78 @@ pub struct SetTimingParametersRequest {
     pub current: Reading,
     pub time: TimeInterval,
 }
-impl_scpi_serialize!(
-    SetTimingParametersRequest,
-    [
-        "TIMEr:SET ",
-        channel,
-        ",",
-        group,
-        ",",
-        voltage,
-        ",",
-        current,
-        ",",
-        time
-    ]
-);
-impl_scpi_request!(SetTimingParametersRequest, EmptyResponse);
+
+impl ScpiRequest for SetTimingParametersRequest {
+    type Response = EmptyResponse;
+
+    fn get_setpoints(&self) -> Setpoints {
+        Setpoints::Values(vec![
+            (Some(self.channel), LimitQuantity::Voltage, self.voltage),
+            (Some(self.channel), LimitQuantity::Current, self.current),
+        ])
+    }
+}
 
 // Command format TIMEr:SET? {CH1|CH2},{1|2|3|4|5};
 // Description Query the voltage/current/time parameters of specified group of specified
 // channel
 // Example TIMEr:SET? CH1,2
 // Typical Return 3,0.5,2
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("TIMEr:SET? ", channel, ",", group), response = GetTimingParametersResponse, crate = "crate")]
 pub struct GetTimingParametersRequest {
     pub channel: Channel,
     pub group: TimingGroup,
 }
 
-impl_scpi_serialize!(
-    GetTimingParametersRequest,
-    ["TIMEr:SET? ", channel, ",", group]
-);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Breaking change: `time` used to be a [`Reading`], which cannot hold times above 65.535 s.
+/// It is an `f32` now, so this response no longer implements `Eq`.
+#[derive(Debug, Clone, Copy, PartialEq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(voltage, ",", current, ",", time, "\n"), crate = "crate")]
 pub struct GetTimingParametersResponse {
     pub voltage: Reading,
     pub current: Reading,
-    pub time: Reading,
-}
-
-impl ScpiDeserialize for GetTimingParametersResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let voltage = Reading::deserialize(input)?;
-        match_literal(input, ",")?;
-        let current = Reading::deserialize(input)?;
-        match_literal(input, ",")?;
-        let time = Reading::deserialize(input)?;
-
-        Ok(GetTimingParametersResponse {
-            voltage,
-            current,
-            time,
-        })
-    }
+    /// In seconds, up to [`TimeInterval::MAX`].
+    pub time: f32,
 }
 
-impl_scpi_request!(GetTimingParametersRequest, GetTimingParametersResponse);
-
 // Command format TIMEr {CH1|CH2},{ON|OFF};
 // Description Turn on/off Timer function of specified channel
 // Example TIMEr CH1,ON
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("TIMEr ", channel, ",", state), crate = "crate")]
 pub struct SetTimerStateRequest {
     pub channel: Channel,
     pub state: State,
 }
-impl_scpi_serialize!(SetTimerStateRequest, ["TIMEr ", channel, ",", state]);
-impl_scpi_request!(SetTimerStateRequest, EmptyResponse);
 
 // 10. SYSTem
 // Command format SYSTem:ERRor?
 // Description Query the error code and the information of the equipment.
 // Typical Return 0 No Error
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("SYSTem:ERRor?"), response = SystemErrorResponse, crate = "crate")]
 pub struct SystemErrorRequest;
-impl_scpi_serialize!(SystemErrorRequest, ["SYSTem:ERRor?"]);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(content, "\n"), crate = "crate")]
 pub struct SystemErrorResponse {
     pub content: String,
 }
 
-impl ScpiDeserialize for SystemErrorResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        Ok(SystemErrorResponse {
-            content: read_all(input)?,
-        })
-    }
-}
-
-impl_scpi_request!(SystemErrorRequest, SystemErrorResponse);
-
 // Command format SYSTem:VERSion?
 // Description Query the software version of the equipment
 // Typical Return 1.01.01.01.02
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("SYSTem:VERSion?"), response = SystemVersionResponse, crate = "crate")]
 pub struct SystemVersionRequest;
-impl_scpi_serialize!(SystemVersionRequest, ["SYSTem:VERSion?"]);
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(version, "\n"), crate = "crate")]
 pub struct SystemVersionResponse {
     pub version: String,
 }
 
-impl ScpiDeserialize for SystemVersionResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        Ok(SystemVersionResponse {
-            version: input.to_string(),
-        })
-    }
-}
-
-impl_scpi_request!(SystemVersionRequest, SystemVersionResponse);
-
 // Command format SYSTem:STATus?
 // Description Query the current working state of the equipment.
 // Typical Return 0x0224
@@ -624,9 +594,9 @@ impl_scpi_request!(SystemVersionRequest, SystemVersionResponse);
 // 7 0: TIMER2 OFF; 1: TIMER2 ON
 // 8 0: CH1 digital display; 1: CH1 waveform display
 // 9 0: CH2 digital display; 1: CH2 waveform display
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("SYSTem:STATus?"), response = SystemStatusResponse, crate = "crate")]
 pub struct SystemStatusRequest;
-impl_scpi_serialize!(SystemStatusRequest, ["SYSTem:STATus?"]);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChannelMode {
@@ -731,6 +701,13 @@ impl SystemStatusResponse {
     }
 }
 
+impl ScpiSerialize for SystemStatusResponse {
+    fn serialize(&self, out: &mut String) {
+        use std::fmt::Write;
+        writeln!(out, "0x{:04X}", self.value).expect("Failed to format number");
+    }
+}
+
 impl ScpiDeserialize for SystemStatusResponse {
     fn deserialize(input: &mut &str) -> Result<Self, Error> {
         match_literal(input, "0x")?;
@@ -742,19 +719,16 @@ impl ScpiDeserialize for SystemStatusResponse {
     }
 }
 
-impl_scpi_request!(SystemStatusRequest, SystemStatusResponse);
-
 // 11. IPaddr
 // Command format IPaddr <IP address>
 // Description Assign a static Internet Protocol (IP) address for the instrument
 // Example IPaddr 10.11.13.214
 // Note The command is invalid when the state of DHCP is on
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("IPaddr ", addr), crate = "crate")]
 pub struct SetIpAddressRequest {
     pub addr: Ipv4Addr,
 }
-impl_scpi_serialize!(SetIpAddressRequest, ["IPaddr ", addr]);
-impl_scpi_request!(SetIpAddressRequest, EmptyResponse);
 
 impl ScpiSerialize for Ipv4Addr {
     fn serialize(&self, out: &mut String) {
@@ -792,127 +766,93 @@ impl ScpiDeserialize for Ipv4Addr {
 // Command format IPaddr?
 // Description Query the current IP address of the instrument
 // Typical Return 10.11.13.214
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("IPaddr?"), response = GetIpAddressResponse, crate = "crate")]
 pub struct GetIpAddressRequest;
-impl_scpi_serialize!(GetIpAddressRequest, ["IPaddr?"]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(address, "\n"), crate = "crate")]
 pub struct GetIpAddressResponse {
     pub address: Ipv4Addr,
 }
-impl ScpiDeserialize for GetIpAddressResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let address = Ipv4Addr::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(GetIpAddressResponse { address })
-    }
-}
-
-impl_scpi_request!(GetIpAddressRequest, GetIpAddressResponse);
 
 // 12. MASKaddr
 // Command format MASKaddr <NetMasK>
 // Description Assign a subnet mask for the instrument
 // Example MASKadd 255.255.255.0
 // Note The command is invalid when the state of DHCP is on
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("MASKaddr ", mask), crate = "crate")]
 pub struct SetSubnetMaskRequest {
     pub mask: Ipv4Addr,
 }
-impl_scpi_serialize!(SetSubnetMaskRequest, ["MASKaddr ", mask]);
-impl_scpi_request!(SetSubnetMaskRequest, EmptyResponse);
 
 // Command format MASKaddr?
 // Description Query the current subnet mask of the instrument
 // Typical Return 255.255.255.0
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("MASKaddr?"), response = GetSubnetMaskResponse, crate = "crate")]
 pub struct GetSubnetMaskRequest;
-impl_scpi_serialize!(GetSubnetMaskRequest, ["MASKaddr?"]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(mask, "\n"), crate = "crate")]
 pub struct GetSubnetMaskResponse {
     pub mask: Ipv4Addr,
 }
-impl ScpiDeserialize for GetSubnetMaskResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let mask = Ipv4Addr::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(GetSubnetMaskResponse { mask })
-    }
-}
-
-impl_scpi_request!(GetSubnetMaskRequest, GetSubnetMaskResponse);
 
 // 13. GATEaddr
 // Command format GATEaddr <GateWay>
 // Description Assign a gateway for the instrument
 // Example GATEaddr 10.11.13.1
 // Note The command is invalid when the state of DHCP is on
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("GATEaddr ", gateway), crate = "crate")]
 pub struct SetGatewayRequest {
     pub gateway: Ipv4Addr,
 }
-impl_scpi_serialize!(SetGatewayRequest, ["GATEaddr ", gateway]);
-impl_scpi_request!(SetGatewayRequest, EmptyResponse);
 
 // Command format GATEaddr?
 // Description Query the current gateway of the instrument
 // Typical Return 10.11.13.1
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("GATEaddr?"), response = GetGatewayResponse, crate = "crate")]
 pub struct GetGatewayRequest;
-impl_scpi_serialize!(GetGatewayRequest, ["GATEaddr?"]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format(gateway, "\n"), crate = "crate")]
 pub struct GetGatewayResponse {
     pub gateway: Ipv4Addr,
 }
-impl ScpiDeserialize for GetGatewayResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        let gateway = Ipv4Addr::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(GetGatewayResponse { gateway })
-    }
-}
-
-impl_scpi_request!(GetGatewayRequest, GetGatewayResponse);
 
 // 14. DHCP
 // Command format DHCP {ON|OFF}
 // Description Assign the network parameters (such as the IP address) for the instrument
 // automatically.
 // Example DHCP ON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("DHCP ", state), crate = "crate")]
 pub struct SetDhcpRequest {
     pub state: State,
 }
-impl_scpi_serialize!(SetDhcpRequest, ["DHCP ", state]);
-impl_scpi_request!(SetDhcpRequest, EmptyResponse);
 
 // Command format DHCP?
 // Description Query whether the automatic network parameters configuration function is
 // turn on
 // Typical Return DHCP:ON
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize, ScpiRequest)]
+#[scpi(format("DHCP?"), response = GetDhcpResponse, crate = "crate")]
 pub struct GetDhcpRequest;
-impl_scpi_serialize!(GetDhcpRequest, ["DHCP?"]);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ScpiSerialize, ScpiDeserialize)]
+#[scpi(format("DHCP:", state, "\n"), crate = "crate")]
 pub struct GetDhcpResponse {
     pub state: State,
 }
 
-impl ScpiDeserialize for GetDhcpResponse {
-    fn deserialize(input: &mut &str) -> Result<Self, Error> {
-        match_literal(input, "DHCP:")?;
-        let state = State::deserialize(input)?;
-        match_literal(input, "\n")?;
-        Ok(GetDhcpResponse { state })
-    }
-}
-
-impl_scpi_request!(GetDhcpRequest, GetDhcpResponse);
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::MnemonicStyle;
 
     #[test]
     fn test_idn() {
@@ -928,4 +868,83 @@ mod tests {
         assert_eq!(response.software_version, "1.01.01.01.02");
         assert_eq!(response.hardware_version, "V3.0");
     }
+
+    #[test]
+    fn test_limit_optional_channel() {
+        let mut out = String::new();
+        SetLimitRequest {
+            quantity: LimitQuantity::Voltage,
+            value: Reading::from(3.3),
+            channel: Some(Channel::One),
+        }
+        .serialize(&mut out);
+        assert_eq!(out, "CH1:VOLTage 3.300");
+
+        let mut out = String::new();
+        GetLimitRequest {
+            quantity: LimitQuantity::Current,
+            channel: None,
+        }
+        .serialize(&mut out);
+        assert_eq!(out, "CURRent?");
+    }
+
+    #[test]
+    fn test_mnemonic_style() {
+        let request = MeasureRequest {
+            quantity: Quantity::Voltage,
+            channel: Some(Channel::Two),
+        };
+
+        let mut out = String::new();
+        request.serialize_styled(&mut out, MnemonicStyle::Short);
+        assert_eq!(out, "MEAS:VOLT? CH2");
+
+        let mut out = String::new();
+        request.serialize_styled(&mut out, MnemonicStyle::Upper);
+        assert_eq!(out, "MEASURE:VOLTAGE? CH2");
+
+        let mut out = String::new();
+        GetIpAddressRequest.serialize_styled(&mut out, MnemonicStyle::Short);
+        assert_eq!(out, "IP?");
+    }
+
+    #[test]
+    fn test_optional_part_deserialize() {
+        #[derive(Debug, PartialEq, ScpiDeserialize)]
+        #[scpi(format([channel, ":"], quantity, "?"), crate = "crate")]
+        struct Query {
+            quantity: LimitQuantity,
+            channel: Option<Channel>,
+        }
+
+        let query = Query::deserialize(&mut "CH2:CURRent?").unwrap();
+        assert_eq!(query.channel, Some(Channel::Two));
+        assert_eq!(query.quantity, LimitQuantity::Current);
+
+        let query = Query::deserialize(&mut "VOLTage?").unwrap();
+        assert_eq!(query.channel, None);
+        assert_eq!(query.quantity, LimitQuantity::Voltage);
+    }
+
+    #[test]
+    fn test_timing_parameters_response() {
+        let response =
+            GetTimingParametersResponse::deserialize(&mut "3.000,0.500,2.000\n").unwrap();
+        assert_eq!(response.voltage, Reading::from_millis(3000));
+        assert_eq!(response.current, Reading::from_millis(500));
+        assert_eq!(response.time, 2.0);
+
+        // Beyond the range of a reading.
+        let response =
+            GetTimingParametersResponse::deserialize(&mut "1.000,0.500,100.000\n").unwrap();
+        assert_eq!(response.time, 100.0);
+    }
+
+    #[test]
+    fn test_dhcp_response() {
+        let response = GetDhcpResponse::deserialize(&mut "DHCP:ON\n").unwrap();
+        assert_eq!(response.state, State::On);
+        assert!(GetDhcpResponse::deserialize(&mut "DHCP:MAYBE\n").is_err());
+    }
 }
@@ -0,0 +1,163 @@
+//! Switching all outputs of all registered supplies off as fast as possible.
+//!
+//! Requests on one [`Spd3303x`] are serialized, so a regular "all off" waits behind pending
+//! queries. An [`EmergencyStopHandle`] writes directly to the connection instead, in between
+//! the lines of other requests. As the output requests are not answered, the responses of
+//! pending queries are not affected.
+
+use std::{
+    future::Future,
+    sync::{Arc, PoisonError},
+};
+
+use futures_util::future::join_all;
+use tokio::{
+    io::{AsyncWriteExt, WriteHalf},
+    net::TcpStream,
+    sync::Mutex,
+    task::JoinHandle,
+};
+
+use crate::{
+    MnemonicStyle, Result, ScpiSerialize,
+    commands::{OutputChannel, SetOutputStateRequest, State},
+    spd3303x::Spd3303x,
+};
+
+/// See [`Spd3303x::emergency_stop_handle`].
+#[derive(Clone)]
+pub struct EmergencyStopHandle {
+    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+}
+
+impl EmergencyStopHandle {
+    pub(crate) fn new(writer: Arc<Mutex<WriteHalf<TcpStream>>>) -> Self {
+        EmergencyStopHandle { writer }
+    }
+
+    /// Writes the requests for all three outputs at once, waiting only for a line
+    /// currently being written.
+    pub async fn all_outputs_off(&self) -> Result<()> {
+        let lines = all_outputs_off_lines();
+        let mut writer = self.writer.lock().await;
+        writer.write_all(lines.as_bytes()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+fn all_outputs_off_lines() -> String {
+    let mut out = String::with_capacity(64);
+    for channel in [OutputChannel::One, OutputChannel::Two, OutputChannel::Three] {
+        SetOutputStateRequest {
+            channel,
+            state: State::Off,
+        }
+        .serialize_styled(&mut out, MnemonicStyle::Short);
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Clone)]
+enum Target {
+    Handle(EmergencyStopHandle),
+    /// Connected only when triggered, e.g. for supplies controlled by another process.
+    Host(String),
+}
+
+/// Registry of supplies, cheap to clone and share with e.g. a signal handler task.
+#[derive(Clone, Default)]
+pub struct EmergencyStop {
+    targets: Arc<std::sync::Mutex<Vec<Target>>>,
+}
+
+impl EmergencyStop {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, handle: EmergencyStopHandle) {
+        self.push(Target::Handle(handle));
+    }
+
+    /// Registers a supply by host, a dedicated connection is established when triggered.
+    pub fn register_host(&self, host: impl Into<String>) {
+        self.push(Target::Host(host.into()));
+    }
+
+    fn push(&self, target: Target) {
+        self.targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(target);
+    }
+
+    /// Switches all outputs of all registered supplies off concurrently,
+    /// returning the results in order of registration.
+    pub async fn trigger(&self) -> Vec<Result<()>> {
+        let targets = self
+            .targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        join_all(targets.into_iter().map(|target| async move {
+            match target {
+                Target::Handle(handle) => handle.all_outputs_off().await,
+                Target::Host(host) => {
+                    Spd3303x::connect_hostname(&host)
+                        .await?
+                        .emergency_stop_handle()
+                        .all_outputs_off()
+                        .await
+                }
+            }
+        }))
+        .await
+    }
+
+    /// Triggers once `signal` completes, e.g. `tokio::signal::ctrl_c()`.
+    pub fn arm<T>(
+        &self,
+        signal: impl Future<Output = T> + Send + 'static,
+    ) -> JoinHandle<Vec<Result<()>>> {
+        let stop = self.clone();
+        tokio::spawn(async move {
+            signal.await;
+            stop.trigger().await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    use super::*;
+
+    #[test]
+    fn test_all_outputs_off_lines() {
+        assert_eq!(
+            all_outputs_off_lines(),
+            "OUTP CH1,OFF\nOUTP CH2,OFF\nOUTP CH3,OFF\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trigger() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let spd = Spd3303x::connect_address(address).await.unwrap();
+        let (mut device, _) = listener.accept().await.unwrap();
+
+        let stop = EmergencyStop::new();
+        stop.register(spd.emergency_stop_handle());
+        let results = stop.trigger().await;
+        assert!(results.iter().all(Result::is_ok));
+
+        let mut received = vec![0; all_outputs_off_lines().len()];
+        device.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, all_outputs_off_lines().as_bytes());
+    }
+}
@@ -0,0 +1,347 @@
+//! Closed-loop source emulation: the voltage setpoint follows a model of a battery, solar panel
+//! or Thevenin source from the measured current, see [`ChannelControl::emulate`].
+//!
+//! [`ChannelControl::emulate`]: crate::channel_control::ChannelControl::emulate
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{Mutex, watch},
+    task::JoinHandle,
+    time::{Instant, MissedTickBehavior, interval},
+};
+
+use crate::{
+    Result,
+    commands::{Channel, LimitQuantity, Quantity, Reading},
+    sampling::check_period,
+    spd3303x::Spd3303x,
+    timer_program::{MAX_CURRENT, MAX_VOLTAGE},
+};
+
+/// Terminal voltage of a source from the current drawn.
+pub trait SourceModel: Send + 'static {
+    /// Voltage for `current` in ampere, drawn for `elapsed` since the previous call.
+    fn voltage(&mut self, current: f32, elapsed: Duration) -> f32;
+
+    /// Current limit the source imposes, e.g. the short circuit current of a solar panel.
+    fn current_limit(&self) -> Option<f32> {
+        None
+    }
+
+    fn state_of_charge(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Ideal voltage source behind a series resistance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thevenin {
+    pub open_circuit_voltage: f32,
+    /// In ohm.
+    pub resistance: f32,
+}
+
+impl SourceModel for Thevenin {
+    fn voltage(&mut self, current: f32, _elapsed: Duration) -> f32 {
+        self.open_circuit_voltage - current * self.resistance
+    }
+}
+
+/// Open circuit voltage of a Li-ion cell over state of charge, typical for NMC cells.
+pub const LI_ION_CURVE: [(f32, f32); 12] = [
+    (0.0, 3.0),
+    (0.05, 3.3),
+    (0.1, 3.5),
+    (0.2, 3.6),
+    (0.3, 3.7),
+    (0.4, 3.75),
+    (0.5, 3.8),
+    (0.6, 3.85),
+    (0.7, 3.92),
+    (0.8, 4.0),
+    (0.9, 4.08),
+    (1.0, 4.2),
+];
+
+/// Li-ion pack of `cells` in series, discharged by the measured current.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiIonBattery {
+    pub cells: u32,
+    pub capacity_ah: f32,
+    /// From 0 to 1.
+    pub state_of_charge: f32,
+    /// Of the whole pack, in ohm.
+    pub internal_resistance: f32,
+    /// State of charge and open circuit voltage per cell, in ascending state of charge.
+    pub curve: Vec<(f32, f32)>,
+}
+
+impl LiIonBattery {
+    pub fn new(cells: u32, capacity_ah: f32, state_of_charge: f32) -> Self {
+        LiIonBattery {
+            cells,
+            capacity_ah,
+            state_of_charge: state_of_charge.clamp(0.0, 1.0),
+            internal_resistance: 0.05 * cells as f32,
+            curve: LI_ION_CURVE.to_vec(),
+        }
+    }
+
+    /// Open circuit voltage of the pack, linearly interpolated from the curve.
+    pub fn open_circuit_voltage(&self) -> f32 {
+        let soc = self.state_of_charge;
+        let cell = match self.curve.iter().position(|(point, _)| *point >= soc) {
+            Some(0) => self.curve[0].1,
+            Some(index) => {
+                let ((soc_0, volts_0), (soc_1, volts_1)) =
+                    (self.curve[index - 1], self.curve[index]);
+                volts_0 + (volts_1 - volts_0) * (soc - soc_0) / (soc_1 - soc_0)
+            }
+            None => self
+                .curve
+                .last()
+                .map(|(_, volts)| *volts)
+                .unwrap_or_default(),
+        };
+        cell * self.cells as f32
+    }
+}
+
+impl SourceModel for LiIonBattery {
+    fn voltage(&mut self, current: f32, elapsed: Duration) -> f32 {
+        let discharged = current * elapsed.as_secs_f32() / 3600.0;
+        self.state_of_charge = (self.state_of_charge
+            - discharged / self.capacity_ah.max(f32::EPSILON))
+        .clamp(0.0, 1.0);
+        self.open_circuit_voltage() - current * self.internal_resistance
+    }
+
+    fn state_of_charge(&self) -> Option<f32> {
+        Some(self.state_of_charge)
+    }
+}
+
+/// Single diode model of a solar panel at constant irradiance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarPanel {
+    pub open_circuit_voltage: f32,
+    pub short_circuit_current: f32,
+    /// Ideality factor times thermal voltage times cells in series, in volt. Shapes the knee.
+    pub diode_voltage: f32,
+}
+
+impl SolarPanel {
+    pub fn new(open_circuit_voltage: f32, short_circuit_current: f32) -> Self {
+        SolarPanel {
+            open_circuit_voltage,
+            short_circuit_current,
+            // Typical for crystalline silicon, about 1.2 V for a 36 cell panel of 21.6 V.
+            diode_voltage: open_circuit_voltage / 18.0,
+        }
+    }
+}
+
+impl SourceModel for SolarPanel {
+    fn voltage(&mut self, current: f32, _elapsed: Duration) -> f32 {
+        let diode = self.diode_voltage.max(f32::EPSILON);
+        let saturation =
+            self.short_circuit_current / ((self.open_circuit_voltage / diode).exp() - 1.0);
+        let remaining = self.short_circuit_current - current;
+        if remaining <= 0.0 {
+            return 0.0;
+        }
+        diode * (remaining / saturation + 1.0).ln()
+    }
+
+    fn current_limit(&self) -> Option<f32> {
+        Some(self.short_circuit_current)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmulationConfig {
+    /// Must not be zero.
+    pub period: Duration,
+    /// Overrides the current limit of the model, the current limit is left unchanged if neither
+    /// is set.
+    pub current_limit: Option<Reading>,
+}
+
+impl Default for EmulationConfig {
+    fn default() -> Self {
+        EmulationConfig {
+            period: Duration::from_millis(100),
+            current_limit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EmulationState {
+    /// Last measured current, in ampere.
+    pub current: f32,
+    /// Last voltage setpoint, in volt.
+    pub voltage: f32,
+    pub state_of_charge: Option<f32>,
+    pub updates: u64,
+    /// Failed measurements or setpoint requests, the loop continues with the next period.
+    pub errors: u64,
+}
+
+/// Adjusts the voltage setpoint of one channel from a background task once per period.
+/// The output is not switched. Emulation stops when this is dropped, after the pending
+/// requests were answered, see [`Emulator::stop`] to wait for that.
+pub struct Emulator {
+    state: watch::Sender<EmulationState>,
+    stop: watch::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Emulator {
+    /// Fails with [`Error::InvalidConfig`](crate::Error::InvalidConfig) for a zero period.
+    pub fn spawn(
+        spd: Arc<Mutex<Spd3303x>>,
+        channel: Channel,
+        model: impl SourceModel,
+        config: EmulationConfig,
+    ) -> Result<Self> {
+        check_period("Emulation period", config.period)?;
+        let (state, _) = watch::channel(EmulationState::default());
+        let (stop, stopped) = watch::channel(());
+        let task = tokio::spawn(emulate(spd, channel, model, config, state.clone(), stopped));
+        Ok(Emulator { state, stop, task })
+    }
+
+    /// Stops emulation and waits until no further setpoints are sent.
+    pub async fn stop(self) {
+        let Emulator { stop, task, .. } = self;
+        drop(stop);
+        // The task does not panic, it only ends when stopped.
+        let _ = task.await;
+    }
+
+    pub fn get_state(&self) -> EmulationState {
+        *self.state.borrow()
+    }
+
+    /// Notifies after each period.
+    pub fn subscribe(&self) -> watch::Receiver<EmulationState> {
+        self.state.subscribe()
+    }
+}
+
+/// Setpoint for `voltage` within the range of the channel.
+fn setpoint(voltage: f32) -> Reading {
+    Reading::from(voltage.clamp(0.0, MAX_VOLTAGE.into()))
+}
+
+async fn emulate(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    mut model: impl SourceModel,
+    config: EmulationConfig,
+    state: watch::Sender<EmulationState>,
+    mut stopped: watch::Receiver<()>,
+) {
+    let current_limit = config.current_limit.or(model
+        .current_limit()
+        .map(|limit| Reading::from(limit.min(MAX_CURRENT.into()))));
+    let mut current = 0.0;
+    let mut previous = Instant::now();
+    let mut ticker = interval(config.period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    if let Some(limit) = current_limit {
+        let limited = spd
+            .lock()
+            .await
+            .set_limit(channel, LimitQuantity::Current, limit)
+            .await;
+        if limited.is_err() {
+            state.send_modify(|state| state.errors += 1);
+        }
+    }
+
+    loop {
+        // Only checked between periods, aborting a request would leave its response unread.
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stopped.changed() => return,
+        }
+        let now = Instant::now();
+        let voltage = setpoint(model.voltage(current, now - previous));
+        previous = now;
+
+        let mut spd = spd.lock().await;
+        let applied = spd
+            .set_limit(channel, LimitQuantity::Voltage, voltage)
+            .await;
+        let measured = spd.measure(channel, Quantity::Current).await;
+        drop(spd);
+
+        if let Ok(measured) = measured {
+            current = measured;
+        }
+        state.send_modify(|state| {
+            state.current = current;
+            state.voltage = voltage.into();
+            state.state_of_charge = model.state_of_charge();
+            state.updates += 1;
+            state.errors += u64::from(applied.is_err()) + u64::from(measured.is_err());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thevenin() {
+        let mut source = Thevenin {
+            open_circuit_voltage: 5.0,
+            resistance: 0.5,
+        };
+        assert_eq!(source.voltage(0.0, Duration::ZERO), 5.0);
+        assert_eq!(source.voltage(2.0, Duration::ZERO), 4.0);
+    }
+
+    #[test]
+    fn test_li_ion() {
+        let mut battery = LiIonBattery::new(2, 2.0, 0.5);
+        assert!((battery.open_circuit_voltage() - 7.6).abs() < 1e-4);
+        battery.state_of_charge = 0.45;
+        assert!((battery.open_circuit_voltage() - 7.55).abs() < 1e-4);
+
+        // 1 A for an hour discharges half of 2 Ah.
+        battery.state_of_charge = 1.0;
+        let voltage = battery.voltage(1.0, Duration::from_secs(3600));
+        assert!((battery.state_of_charge - 0.5).abs() < 1e-4);
+        assert!((voltage - (7.6 - 0.1)).abs() < 1e-4);
+
+        battery.voltage(1.0, Duration::from_secs(7200));
+        assert_eq!(battery.state_of_charge(), Some(0.0));
+        assert!((battery.open_circuit_voltage() - 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_solar_panel() {
+        let mut panel = SolarPanel::new(21.6, 1.0);
+        assert!((panel.voltage(0.0, Duration::ZERO) - 21.6).abs() < 1e-3);
+        assert_eq!(panel.voltage(1.0, Duration::ZERO), 0.0);
+
+        // Voltage stays high until the knee, then collapses towards short circuit.
+        let half = panel.voltage(0.5, Duration::ZERO);
+        let near_short = panel.voltage(0.99, Duration::ZERO);
+        assert!(half > 19.0 && half < 21.6);
+        assert!(near_short < half - 3.0);
+        assert_eq!(panel.current_limit(), Some(1.0));
+    }
+
+    #[test]
+    fn test_setpoint_clamped() {
+        assert_eq!(setpoint(-1.0), Reading::from(0.0));
+        assert_eq!(setpoint(40.0), MAX_VOLTAGE);
+    }
+}
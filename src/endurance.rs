@@ -0,0 +1,487 @@
+//! Power-cycling endurance runs with per-cycle current capture, anomaly flags and progress
+//! persisted for resuming, see [`run_endurance`].
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    sync::Mutex,
+    time::{Instant, MissedTickBehavior, interval, sleep_until},
+};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, OutputChannel, Quantity, State},
+    sampling::check_period,
+    spd3303x::Spd3303x,
+    statistics::Statistics,
+};
+
+/// Cycles flagged when a current is outside the limits, all in ampere.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AnomalyLimits {
+    pub max_inrush: Option<f32>,
+    pub min_steady: Option<f32>,
+    pub max_steady: Option<f32>,
+    /// Relative deviation of the steady current from the mean of the previous normal cycles,
+    /// checked once `warm_up` normal cycles were recorded.
+    pub max_deviation: Option<f32>,
+    pub warm_up: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnduranceConfig {
+    /// Switched on in order and off in reverse order. Currents are captured for CH1 and CH2,
+    /// CH3 cannot be measured.
+    pub outputs: Vec<OutputChannel>,
+    pub cycles: u32,
+    pub on_time: Duration,
+    pub off_time: Duration,
+    /// Current is polled during this window after switching on, the peak is the inrush current.
+    /// Polling is limited by the device, short peaks may be missed.
+    pub inrush_window: Duration,
+    pub sample_period: Duration,
+    pub limits: AnomalyLimits,
+    /// JSON lines file of completed cycles. An existing file is resumed from.
+    pub progress: Option<PathBuf>,
+}
+
+impl EnduranceConfig {
+    pub fn new(
+        outputs: Vec<OutputChannel>,
+        cycles: u32,
+        on_time: Duration,
+        off_time: Duration,
+    ) -> Self {
+        EnduranceConfig {
+            outputs,
+            cycles,
+            on_time,
+            off_time,
+            inrush_window: Duration::from_millis(200),
+            sample_period: Duration::from_millis(20),
+            limits: AnomalyLimits {
+                warm_up: 5,
+                ..Default::default()
+            },
+            progress: None,
+        }
+    }
+
+    fn measured_channels(&self) -> Vec<Channel> {
+        self.outputs
+            .iter()
+            .filter_map(|output| Channel::try_from(*output).ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelCycle {
+    pub channel: Channel,
+    pub inrush: f32,
+    pub steady: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CycleRecord {
+    /// Counted from zero.
+    pub cycle: u32,
+    pub timestamp: SystemTime,
+    pub channels: Vec<ChannelCycle>,
+    /// Empty for normal cycles.
+    pub anomalies: Vec<String>,
+}
+
+impl CycleRecord {
+    pub fn to_json(&self) -> String {
+        let channels = self
+            .channels
+            .iter()
+            .map(|channel| {
+                serde_json::json!({
+                    "channel": format!("{:?}", channel.channel),
+                    "inrush": channel.inrush,
+                    "steady": channel.steady,
+                })
+            })
+            .collect::<Vec<_>>();
+        let timestamp = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        serde_json::json!({
+            "cycle": self.cycle,
+            "timestamp": timestamp,
+            "channels": channels,
+            "anomalies": self.anomalies,
+        })
+        .to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| Error::Other(format!("Invalid cycle record {reason}: `{json}`"));
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| invalid(&e.to_string()))?;
+        let channels = value["channels"]
+            .as_array()
+            .ok_or(invalid("without channels"))?
+            .iter()
+            .map(|channel| {
+                let number = |key: &str| {
+                    channel[key]
+                        .as_f64()
+                        .map(|value| value as f32)
+                        .ok_or(invalid(&format!("without `{key}`")))
+                };
+                let name = match channel["channel"].as_str() {
+                    Some("One") => Channel::One,
+                    Some("Two") => Channel::Two,
+                    _ => return Err(invalid("with unknown channel")),
+                };
+                Ok(ChannelCycle {
+                    channel: name,
+                    inrush: number("inrush")?,
+                    steady: number("steady")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let timestamp = value["timestamp"]
+            .as_f64()
+            .ok_or(invalid("without timestamp"))?;
+        Ok(CycleRecord {
+            cycle: value["cycle"].as_u64().ok_or(invalid("without cycle"))? as u32,
+            timestamp: UNIX_EPOCH + Duration::from_secs_f64(timestamp.max(0.0)),
+            channels,
+            anomalies: value["anomalies"]
+                .as_array()
+                .ok_or(invalid("without anomalies"))?
+                .iter()
+                .filter_map(|anomaly| anomaly.as_str().map(str::to_string))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSummary {
+    pub channel: Channel,
+    pub inrush: Option<Statistics>,
+    pub steady: Option<Statistics>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnduranceSummary {
+    pub cycles: u32,
+    /// Cycles loaded from the progress file.
+    pub resumed: u32,
+    pub anomalous_cycles: Vec<u32>,
+    pub channels: Vec<ChannelSummary>,
+}
+
+impl EnduranceSummary {
+    pub fn new(records: &[CycleRecord], resumed: u32) -> Self {
+        let mut channels = records
+            .iter()
+            .flat_map(|record| record.channels.iter().map(|channel| channel.channel))
+            .collect::<Vec<_>>();
+        channels.sort_by_key(|channel| *channel as u8);
+        channels.dedup();
+
+        let values = |channel: Channel, value: fn(&ChannelCycle) -> f32| {
+            records
+                .iter()
+                .flat_map(|record| &record.channels)
+                .filter(move |cycle| cycle.channel == channel)
+                .map(value)
+        };
+        EnduranceSummary {
+            cycles: records.len() as u32,
+            resumed,
+            anomalous_cycles: records
+                .iter()
+                .filter(|record| !record.anomalies.is_empty())
+                .map(|record| record.cycle)
+                .collect(),
+            channels: channels
+                .into_iter()
+                .map(|channel| ChannelSummary {
+                    channel,
+                    inrush: Statistics::from_values(values(channel, |cycle| cycle.inrush)),
+                    steady: Statistics::from_values(values(channel, |cycle| cycle.steady)),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Flags the cycle against the limits and the steady currents of previous normal cycles.
+fn anomalies(
+    limits: &AnomalyLimits,
+    cycle: &[ChannelCycle],
+    previous: &[CycleRecord],
+) -> Vec<String> {
+    let mut anomalies = Vec::new();
+    for current in cycle {
+        let channel = current.channel;
+        if let Some(max) = limits.max_inrush.filter(|max| current.inrush > *max) {
+            anomalies.push(format!(
+                "{channel:?} inrush {:.3} A above {max:.3} A",
+                current.inrush
+            ));
+        }
+        if let Some(min) = limits.min_steady.filter(|min| current.steady < *min) {
+            anomalies.push(format!(
+                "{channel:?} steady {:.3} A below {min:.3} A",
+                current.steady
+            ));
+        }
+        if let Some(max) = limits.max_steady.filter(|max| current.steady > *max) {
+            anomalies.push(format!(
+                "{channel:?} steady {:.3} A above {max:.3} A",
+                current.steady
+            ));
+        }
+
+        let Some(max_deviation) = limits.max_deviation else {
+            continue;
+        };
+        let normal = previous
+            .iter()
+            .filter(|record| record.anomalies.is_empty())
+            .flat_map(|record| &record.channels)
+            .filter(|previous| previous.channel == channel)
+            .map(|previous| previous.steady);
+        let Some(statistics) = Statistics::from_values(normal) else {
+            continue;
+        };
+        if statistics.count < limits.warm_up.max(1) as usize {
+            continue;
+        }
+        let deviation = (current.steady - statistics.mean).abs() / statistics.mean.abs().max(1e-3);
+        if deviation > max_deviation {
+            anomalies.push(format!(
+                "{channel:?} steady {:.3} A deviates {:.0} % from mean {:.3} A",
+                current.steady,
+                deviation * 100.0,
+                statistics.mean
+            ));
+        }
+    }
+    anomalies
+}
+
+/// Reads completed cycles, a truncated last line from an interrupted write is dropped.
+fn load_progress(path: &PathBuf) -> Result<Vec<CycleRecord>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let lines = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    let mut records = Vec::with_capacity(lines.len());
+    for (index, line) in lines.iter().enumerate() {
+        match CycleRecord::from_json(line) {
+            Ok(record) => records.push(record),
+            Err(_) if index + 1 == lines.len() => break,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(records)
+}
+
+/// Rewrites the progress file to drop a truncated last line, then opens it for appending.
+/// The records are written to a temporary file first, which replaces the progress file once
+/// synced, so completed cycles are never lost to an interrupted rewrite.
+fn rewrite_progress(path: &PathBuf, records: &[CycleRecord]) -> Result<File> {
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary)?;
+    for record in records {
+        writeln!(file, "{}", record.to_json())?;
+    }
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(OpenOptions::new().append(true).open(path)?)
+}
+
+/// Power-cycles the outputs of `config`, resuming from its progress file if present.
+/// On a failed request the outputs are switched off and the error is returned; completed
+/// cycles remain in the progress file.
+pub async fn run_endurance(
+    spd: Arc<Mutex<Spd3303x>>,
+    config: &EnduranceConfig,
+) -> Result<EnduranceSummary> {
+    check_period("Sample period", config.sample_period)?;
+    let mut records = match &config.progress {
+        Some(path) => load_progress(path)?,
+        None => Vec::new(),
+    };
+    let resumed = records.len() as u32;
+    let mut progress = match &config.progress {
+        Some(path) => Some(rewrite_progress(path, &records)?),
+        None => None,
+    };
+
+    for cycle in resumed..config.cycles {
+        let channels = match run_cycle(&spd, config).await {
+            Ok(channels) => channels,
+            Err(error) => {
+                let _ = switch_all(&spd, config, State::Off).await;
+                return Err(error);
+            }
+        };
+        let record = CycleRecord {
+            cycle,
+            timestamp: SystemTime::now(),
+            anomalies: anomalies(&config.limits, &channels, &records),
+            channels,
+        };
+        if let Some(progress) = progress.as_mut() {
+            writeln!(progress, "{}", record.to_json())?;
+            progress.flush()?;
+            progress.sync_data()?;
+        }
+        records.push(record);
+    }
+
+    Ok(EnduranceSummary::new(&records, resumed))
+}
+
+async fn switch_all(spd: &Mutex<Spd3303x>, config: &EnduranceConfig, state: State) -> Result<()> {
+    let mut spd = spd.lock().await;
+    let mut result = Ok(());
+    let outputs: Box<dyn Iterator<Item = &OutputChannel>> = match state {
+        State::On => Box::new(config.outputs.iter()),
+        State::Off => Box::new(config.outputs.iter().rev()),
+    };
+    for output in outputs {
+        result = result.and(spd.set_output(*output, state).await);
+    }
+    result
+}
+
+async fn run_cycle(spd: &Mutex<Spd3303x>, config: &EnduranceConfig) -> Result<Vec<ChannelCycle>> {
+    let measured = config.measured_channels();
+    let on = Instant::now();
+    switch_all(spd, config, State::On).await?;
+
+    let mut inrush = vec![0.0f32; measured.len()];
+    let mut ticker = interval(config.sample_period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    while on.elapsed() < config.inrush_window.min(config.on_time) {
+        ticker.tick().await;
+        let mut spd = spd.lock().await;
+        for (channel, peak) in measured.iter().zip(inrush.iter_mut()) {
+            *peak = peak.max(spd.measure(*channel, Quantity::Current).await?);
+        }
+    }
+
+    sleep_until(on + config.on_time).await;
+    let mut channels = Vec::with_capacity(measured.len());
+    {
+        let mut spd = spd.lock().await;
+        for (channel, inrush) in measured.iter().zip(inrush) {
+            let steady = spd.measure(*channel, Quantity::Current).await?;
+            channels.push(ChannelCycle {
+                channel: *channel,
+                inrush: inrush.max(steady),
+                steady,
+            });
+        }
+    }
+
+    let off = Instant::now();
+    switch_all(spd, config, State::Off).await?;
+    sleep_until(off + config.off_time).await;
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(cycle: u32, steady: f32) -> CycleRecord {
+        CycleRecord {
+            cycle,
+            timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            channels: vec![ChannelCycle {
+                channel: Channel::One,
+                inrush: steady * 2.0,
+                steady,
+            }],
+            anomalies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_record_json() {
+        let mut record = record(3, 0.25);
+        record.anomalies.push("One steady too high".to_string());
+        assert_eq!(CycleRecord::from_json(&record.to_json()).unwrap(), record);
+        assert!(CycleRecord::from_json(r#"{"cycle":1}"#).is_err());
+    }
+
+    #[test]
+    fn test_anomalies() {
+        let limits = AnomalyLimits {
+            max_inrush: Some(1.2),
+            max_deviation: Some(0.2),
+            warm_up: 3,
+            ..Default::default()
+        };
+        let previous = (0..3).map(|cycle| record(cycle, 0.5)).collect::<Vec<_>>();
+
+        let normal = record(3, 0.55).channels;
+        assert!(anomalies(&limits, &normal, &previous).is_empty());
+        // Not enough normal cycles for the deviation yet.
+        let warming_up = anomalies(&limits, &record(3, 0.7).channels, &previous[..2]);
+        assert_eq!(warming_up.len(), 1);
+
+        let anomalous = anomalies(&limits, &record(3, 0.7).channels, &previous);
+        assert_eq!(anomalous.len(), 2);
+        assert!(anomalous[0].contains("inrush"));
+        assert!(anomalous[1].contains("deviates"));
+    }
+
+    #[test]
+    fn test_progress_and_summary() {
+        let path =
+            std::env::temp_dir().join(format!("spd3303x-endurance-{}.jsonl", std::process::id()));
+        let mut anomalous = record(1, 0.9);
+        anomalous.anomalies.push("One steady too high".to_string());
+        let content = format!(
+            "{}\n{}\n{{\"cycle\":2,\"chan",
+            record(0, 0.5).to_json(),
+            anomalous.to_json()
+        );
+        fs::write(&path, content).unwrap();
+
+        let records = load_progress(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        let mut progress = rewrite_progress(&path, &records).unwrap();
+        writeln!(progress, "{}", record(2, 0.5).to_json()).unwrap();
+        drop(progress);
+        assert_eq!(load_progress(&path).unwrap().len(), 3);
+        fs::remove_file(&path).unwrap();
+        assert!(load_progress(&path).unwrap().is_empty());
+
+        let summary = EnduranceSummary::new(&records, 2);
+        assert_eq!(summary.cycles, 2);
+        assert_eq!(summary.anomalous_cycles, vec![1]);
+        let steady = summary.channels[0].steady.unwrap();
+        assert_eq!((steady.min, steady.max), (0.5, 0.9));
+    }
+}
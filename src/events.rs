@@ -0,0 +1,197 @@
+//! Typed events for changes of the system status, including changes made at the front panel.
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{Mutex, broadcast, watch},
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+    Result,
+    commands::{
+        Channel, ChannelMode, ChannelStatus, DisplayMode, OperationMode, State, SystemStatus,
+    },
+    sampling::check_period,
+    spd3303x::Spd3303x,
+};
+
+/// Events buffered per subscriber, slower subscribers miss events, see [`broadcast`].
+const CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusEvent {
+    /// Transition between constant voltage and constant current.
+    ModeChanged {
+        channel: Channel,
+        mode: ChannelMode,
+    },
+    OutputChanged {
+        channel: Channel,
+        state: State,
+    },
+    TimerChanged {
+        channel: Channel,
+        state: State,
+    },
+    DisplayChanged {
+        channel: Channel,
+        display: DisplayMode,
+    },
+    OperationModeChanged(OperationMode),
+    /// Polling the status failed, polling continues.
+    PollFailed(String),
+}
+
+/// Events for all differences between two status readings.
+pub fn status_changes(previous: &SystemStatus, current: &SystemStatus) -> Vec<StatusEvent> {
+    let mut events = Vec::new();
+    if previous.operation_mode != current.operation_mode {
+        events.push(StatusEvent::OperationModeChanged(current.operation_mode));
+    }
+    for channel in [Channel::One, Channel::Two] {
+        events.extend(channel_changes(
+            channel,
+            previous.get(channel),
+            current.get(channel),
+        ));
+    }
+    events
+}
+
+fn channel_changes(
+    channel: Channel,
+    previous: &ChannelStatus,
+    current: &ChannelStatus,
+) -> Vec<StatusEvent> {
+    let mut events = Vec::new();
+    if previous.mode != current.mode {
+        events.push(StatusEvent::ModeChanged {
+            channel,
+            mode: current.mode,
+        });
+    }
+    if previous.output != current.output {
+        events.push(StatusEvent::OutputChanged {
+            channel,
+            state: current.output,
+        });
+    }
+    if previous.timer != current.timer {
+        events.push(StatusEvent::TimerChanged {
+            channel,
+            state: current.timer,
+        });
+    }
+    if previous.display != current.display {
+        events.push(StatusEvent::DisplayChanged {
+            channel,
+            display: current.display,
+        });
+    }
+    events
+}
+
+/// Polls the system status in a background task and broadcasts [`StatusEvent`]s to subscribers.
+///
+/// The first reading is the baseline and emits no events. Polling stops when this is dropped,
+/// after the pending request was answered, so the connection stays usable.
+pub struct StatusEvents {
+    sender: broadcast::Sender<StatusEvent>,
+    /// Closed on drop, never sent on.
+    _stop: watch::Sender<()>,
+}
+
+impl StatusEvents {
+    /// Polls over the shared connection, e.g. [`ChannelControl::get_device`] after
+    /// [`Spd3303x::into_channels`]. Fails with [`Error::InvalidConfig`] for a zero `period`.
+    ///
+    /// [`ChannelControl::get_device`]: crate::channel_control::ChannelControl::get_device
+    /// [`Error::InvalidConfig`]: crate::Error::InvalidConfig
+    pub fn spawn(spd: Arc<Mutex<Spd3303x>>, period: Duration) -> Result<Self> {
+        check_period("Status polling period", period)?;
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let (stop, stopped) = watch::channel(());
+        tokio::spawn(poll(spd, period, sender.clone(), stopped));
+        Ok(StatusEvents {
+            sender,
+            _stop: stop,
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StatusEvent> {
+        self.sender.subscribe()
+    }
+}
+
+async fn poll(
+    spd: Arc<Mutex<Spd3303x>>,
+    period: Duration,
+    sender: broadcast::Sender<StatusEvent>,
+    mut stopped: watch::Receiver<()>,
+) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let mut previous: Option<SystemStatus> = None;
+    loop {
+        // Only checked between requests, aborting one would leave its response unread.
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = stopped.changed() => return,
+        }
+        let status = spd.lock().await.get_status().await;
+
+        // Sending only fails without subscribers, events are not kept for later subscribers.
+        match status {
+            Ok(status) => {
+                if let Some(previous) = previous {
+                    for event in status_changes(&previous, &status) {
+                        let _ = sender.send(event);
+                    }
+                }
+                previous = Some(status);
+            }
+            Err(error) => {
+                let _ = sender.send(StatusEvent::PollFailed(error.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::SystemStatusResponse;
+
+    #[test]
+    fn test_status_changes() {
+        let previous = SystemStatusResponse { value: 0x0014 }.decode();
+        assert!(status_changes(&previous, &previous).is_empty());
+
+        // Parallel, CH1 CC with output off and waveform display, CH2 output on
+        let current = SystemStatusResponse { value: 0x0129 }.decode();
+        assert_eq!(
+            status_changes(&previous, &current),
+            vec![
+                StatusEvent::OperationModeChanged(OperationMode::Parallel),
+                StatusEvent::ModeChanged {
+                    channel: Channel::One,
+                    mode: ChannelMode::ConstantCurrent,
+                },
+                StatusEvent::OutputChanged {
+                    channel: Channel::One,
+                    state: State::Off,
+                },
+                StatusEvent::DisplayChanged {
+                    channel: Channel::One,
+                    display: DisplayMode::WaveformDisplay,
+                },
+                StatusEvent::OutputChanged {
+                    channel: Channel::Two,
+                    state: State::On,
+                },
+            ]
+        );
+    }
+}
@@ -18,6 +18,17 @@ impl FixedChannelControl {
         FixedChannelControl { spd, channel }
     }
 
+    pub fn get_channel(&self) -> OutputChannel {
+        self.channel
+    }
+
+    /// The connection shared by all channels, e.g. for [`StatusEvents::spawn`].
+    ///
+    /// [`StatusEvents::spawn`]: crate::events::StatusEvents::spawn
+    pub fn get_device(&self) -> Arc<Mutex<Spd3303x>> {
+        self.spd.clone()
+    }
+
     pub async fn set_output(&self, state: State) -> Result<()> {
         let mut spd = self.spd.lock().await;
         spd.set_output(self.channel, state).await
@@ -0,0 +1,278 @@
+//! I-V curve tracing by sweeping the voltage setpoint, see [`ChannelControl::trace_iv_curve`].
+//!
+//! [`ChannelControl::trace_iv_curve`]: crate::channel_control::ChannelControl::trace_iv_curve
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
+
+use crate::{
+    Result,
+    commands::{Channel, ChannelMode, LimitQuantity, Reading, State},
+    settle::set_voltage_and_settle,
+    snapshot::ChannelSnapshot,
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepConfig {
+    pub start: Reading,
+    pub stop: Reading,
+    /// Number of setpoints including start and stop, at least two.
+    pub points: u32,
+    pub current_limit: Reading,
+    /// A point is settled once the voltage is within `tolerance` of the setpoint, in volt,
+    /// or the channel is in constant current mode.
+    pub tolerance: f32,
+    /// Points not settled within `timeout` are recorded anyway, marked as not settled.
+    pub timeout: Duration,
+    /// Switches the output off after the sweep, also if it failed.
+    pub switch_off: bool,
+}
+
+impl SweepConfig {
+    pub fn new(start: Reading, stop: Reading, points: u32, current_limit: Reading) -> Self {
+        SweepConfig {
+            start,
+            stop,
+            points,
+            current_limit,
+            tolerance: 0.02,
+            timeout: Duration::from_secs(1),
+            switch_off: true,
+        }
+    }
+
+    /// Evenly spaced setpoints from start to stop.
+    pub fn setpoints(&self) -> Vec<Reading> {
+        let points = i64::from(self.points.max(2));
+        let start = i64::from(self.start.get_millis());
+        let stop = i64::from(self.stop.get_millis());
+        (0..points)
+            .map(|point| {
+                Reading::from_millis((start + (stop - start) * point / (points - 1)) as u16)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IvPoint {
+    pub setpoint: f32,
+    pub voltage: f32,
+    pub current: f32,
+    pub power: f32,
+    pub mode: ChannelMode,
+    pub settled: bool,
+}
+
+impl IvPoint {
+    fn new(setpoint: Reading, snapshot: &ChannelSnapshot, settled: bool) -> Self {
+        IvPoint {
+            setpoint: setpoint.into(),
+            voltage: snapshot.voltage,
+            current: snapshot.current,
+            power: snapshot.power,
+            mode: snapshot.mode,
+            settled,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IvCurve {
+    pub channel: Channel,
+    pub current_limit: f32,
+    pub points: Vec<IvPoint>,
+}
+
+impl IvCurve {
+    /// One point per row with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("setpoint,voltage,current,power,mode,settled\n");
+        for point in &self.points {
+            out.push_str(&format!(
+                "{:.3},{:.3},{:.3},{:.3},{:?},{}\n",
+                point.setpoint,
+                point.voltage,
+                point.current,
+                point.power,
+                point.mode,
+                point.settled
+            ));
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let points = self
+            .points
+            .iter()
+            .map(|point| {
+                serde_json::json!({
+                    "setpoint": point.setpoint,
+                    "voltage": point.voltage,
+                    "current": point.current,
+                    "power": point.power,
+                    "mode": format!("{:?}", point.mode),
+                    "settled": point.settled,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "channel": format!("{:?}", self.channel),
+            "current_limit": self.current_limit,
+            "points": points,
+        })
+        .to_string()
+    }
+
+    /// Measured current over measured voltage, axes scaled from zero to the maxima.
+    pub fn to_svg(&self, width: u32, height: u32) -> String {
+        const MARGIN: f32 = 40.0;
+        let (width, height) = (width as f32, height as f32);
+        let max =
+            |value: fn(&IvPoint) -> f32| self.points.iter().map(value).fold(f32::EPSILON, f32::max);
+        let (max_voltage, max_current) = (max(|point| point.voltage), max(|point| point.current));
+        let x = |voltage: f32| MARGIN + voltage / max_voltage * (width - 2.0 * MARGIN);
+        let y = |current: f32| height - MARGIN - current / max_current * (height - 2.0 * MARGIN);
+
+        let polyline = self
+            .points
+            .iter()
+            .map(|point| format!("{:.1},{:.1}", x(point.voltage), y(point.current)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (left, right, top, bottom) = (x(0.0), x(max_voltage), y(max_current), y(0.0));
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n\
+             <polyline points=\"{left:.1},{top:.1} {left:.1},{bottom:.1} {right:.1},{bottom:.1}\" fill=\"none\" stroke=\"black\"/>\n\
+             <text x=\"{right:.1}\" y=\"{:.1}\" text-anchor=\"end\">{max_voltage:.3} V</text>\n\
+             <text x=\"{:.1}\" y=\"{top:.1}\">{max_current:.3} A</text>\n\
+             <polyline points=\"{polyline}\" fill=\"none\" stroke=\"blue\"/>\n\
+             </svg>\n",
+            bottom + 20.0,
+            left + 5.0,
+        )
+    }
+}
+
+/// Sets the current limit, switches the output on and steps the voltage setpoint over the
+/// sweep, recording one point per setpoint once settled.
+pub async fn trace_iv_curve(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: &SweepConfig,
+) -> Result<IvCurve> {
+    let curve = sweep(spd.clone(), channel, config).await;
+    if config.switch_off {
+        let switched = spd
+            .lock()
+            .await
+            .set_output(channel.into(), State::Off)
+            .await;
+        return curve.and_then(|curve| switched.map(|_| curve));
+    }
+    curve
+}
+
+async fn sweep(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: &SweepConfig,
+) -> Result<IvCurve> {
+    let setpoints = config.setpoints();
+    {
+        let mut spd = spd.lock().await;
+        spd.set_limit(channel, LimitQuantity::Current, config.current_limit)
+            .await?;
+        spd.set_limit(channel, LimitQuantity::Voltage, setpoints[0])
+            .await?;
+        spd.set_output(channel.into(), State::On).await?;
+    }
+
+    let mut points = Vec::with_capacity(setpoints.len());
+    for setpoint in setpoints {
+        let (snapshot, settled) = set_voltage_and_settle(
+            spd.clone(),
+            channel,
+            setpoint,
+            config.tolerance,
+            config.timeout,
+        )
+        .await?;
+        points.push(IvPoint::new(setpoint, &snapshot, settled));
+    }
+
+    Ok(IvCurve {
+        channel,
+        current_limit: config.current_limit.into(),
+        points,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve() -> IvCurve {
+        let point = |voltage: f32, current: f32, mode| IvPoint {
+            setpoint: voltage,
+            voltage,
+            current,
+            power: voltage * current,
+            mode,
+            settled: true,
+        };
+        IvCurve {
+            channel: Channel::One,
+            current_limit: 0.02,
+            points: vec![
+                point(1.0, 0.0, ChannelMode::ConstantVoltage),
+                point(2.0, 0.02, ChannelMode::ConstantCurrent),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_setpoints() {
+        let config = SweepConfig::new(1.0.into(), 3.0.into(), 5, 0.02.into());
+        assert_eq!(
+            config.setpoints(),
+            [1000, 1500, 2000, 2500, 3000]
+                .map(Reading::from_millis)
+                .to_vec()
+        );
+        let config = SweepConfig::new(3.0.into(), 1.0.into(), 1, 0.02.into());
+        assert_eq!(
+            config.setpoints(),
+            [3000, 1000].map(Reading::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            curve().to_csv(),
+            "setpoint,voltage,current,power,mode,settled\n\
+             1.000,1.000,0.000,0.000,ConstantVoltage,true\n\
+             2.000,2.000,0.020,0.040,ConstantCurrent,true\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let json: serde_json::Value = serde_json::from_str(&curve().to_json()).unwrap();
+        assert_eq!(json["channel"], "One");
+        assert_eq!(json["points"].as_array().unwrap().len(), 2);
+        assert_eq!(json["points"][1]["mode"], "ConstantCurrent");
+    }
+
+    #[test]
+    fn test_svg() {
+        let svg = curve().to_svg(400, 300);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("points=\"200.0,260.0 360.0,40.0\""));
+        assert!(svg.contains("2.000 V"));
+    }
+}
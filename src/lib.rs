@@ -1,43 +1,127 @@
 #![feature(pattern)]
 
+use safety::Setpoints;
 use std::str::pattern::{Pattern, Searcher};
 use thiserror::Error;
 
+pub use spd3303x_derive::{ScpiDeserialize, ScpiRequest, ScpiSerialize};
+
+pub mod batch;
+pub mod capture;
 pub mod channel_control;
+pub mod codec;
 pub mod commands;
+pub mod emergency_stop;
+pub mod emulation;
+pub mod endurance;
+pub mod events;
 pub mod fixed_channel_control;
+pub mod iv_curve;
+pub mod logger;
+pub mod output_guard;
+pub mod profiles;
+pub mod protection;
+pub mod ramp;
+pub mod safety;
+pub mod sampling;
+pub mod sequencing;
+pub mod settle;
+pub mod snapshot;
 pub mod spd3303x;
+pub mod statistics;
+pub mod threshold;
+pub mod timer_program;
+pub mod watchdog;
+pub mod waveform;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Received data does not match expected format: {0}")]
     ResponseDecoding(String),
+    #[error("Received request does not match any known command: {0}")]
+    RequestDecoding(String),
     #[error("Underlying I/O error: {0}")]
     IoError(#[from] std::io::Error),
     #[error("Failed to connect: {0}")]
     ConnectFailed(String),
     #[error("Serial mismatch: {0}")]
     SerialMismatch(String),
+    #[error("Safety limit exceeded: {0}")]
+    SafetyLimit(String),
+    #[error("Ramp aborted: {0}")]
+    RampAborted(String),
+    #[error("Sequence failed: {0}")]
+    SequenceFailed(String),
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Invalid timer program: {0}")]
+    InvalidProgram(String),
+    #[error("Invalid waveform: {0}")]
+    InvalidWaveform(String),
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
     #[error("Other: {0}")]
     Other(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Spelling of mnemonics (keywords) in serialized requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MnemonicStyle {
+    /// As documented, e.g. `MEASure:VOLTage?`.
+    #[default]
+    Long,
+    /// Upper case part only, e.g. `MEAS:VOLT?`.
+    Short,
+    /// Long form in upper case, e.g. `MEASURE:VOLTAGE?`.
+    Upper,
+}
+
 pub trait ScpiSerialize {
     fn serialize(&self, out: &mut String);
+
+    /// Serializes with mnemonics spelled in `style`.
+    /// Defaults to [`ScpiSerialize::serialize`], for types without mnemonics.
+    fn serialize_styled(&self, out: &mut String, _style: MnemonicStyle) {
+        self.serialize(out);
+    }
 }
 
 pub trait ScpiDeserialize
 where
     Self: Sized,
 {
+    /// Whether the device sends no response at all, see [`EmptyResponse`].
+    const EMPTY: bool = false;
+
     fn deserialize(input: &mut &str) -> Result<Self>;
 }
 
 // Rename Query
 pub trait ScpiRequest: ScpiSerialize {
     type Response: ScpiDeserialize;
+
+    /// Decodes a complete command line, e.g. for a simulator, failing with
+    /// [`Error::RequestDecoding`].
+    fn parse(line: &str) -> Result<Self>
+    where
+        Self: ScpiDeserialize,
+    {
+        let mut input = line.trim();
+        Self::deserialize(&mut input)
+            .and_then(|request| check_empty(&mut input).map(|_| request))
+            .map_err(|error| match error {
+                Error::ResponseDecoding(message) => Error::RequestDecoding(message),
+                other => other,
+            })
+    }
+
+    /// Setpoints written by this request, checked against the safety limits by
+    /// [`Spd3303x::execute_batch`](crate::spd3303x::Spd3303x::execute_batch).
+    fn get_setpoints(&self) -> Setpoints {
+        Setpoints::Unchanged
+    }
 }
 
 impl<T: ScpiSerialize> ScpiSerialize for Option<T> {
@@ -46,10 +130,46 @@ impl<T: ScpiSerialize> ScpiSerialize for Option<T> {
             inner.serialize(out);
         }
     }
+
+    fn serialize_styled(&self, out: &mut String, style: MnemonicStyle) {
+        if let Some(inner) = self {
+            inner.serialize_styled(out, style);
+        }
+    }
+}
+
+/// `None` for empty input, other decoding errors are returned.
+impl<T: ScpiDeserialize> ScpiDeserialize for Option<T> {
+    fn deserialize(input: &mut &str) -> Result<Self> {
+        if input.trim().is_empty() {
+            return Ok(None);
+        }
+        T::deserialize(input).map(Some)
+    }
+}
+
+impl ScpiSerialize for String {
+    fn serialize(&self, out: &mut String) {
+        out.push_str(self);
+    }
+}
+
+/// Consumes the remaining input, surrounding whitespace is trimmed.
+impl ScpiDeserialize for String {
+    fn deserialize(input: &mut &str) -> Result<Self> {
+        let value = input.trim().to_string();
+        *input = "";
+        Ok(value)
+    }
 }
 
 pub struct EmptyResponse;
+impl ScpiSerialize for EmptyResponse {
+    fn serialize(&self, _out: &mut String) {}
+}
 impl ScpiDeserialize for EmptyResponse {
+    const EMPTY: bool = true;
+
     fn deserialize(_input: &mut &str) -> Result<Self> {
         Ok(EmptyResponse)
     }
@@ -65,26 +185,48 @@ impl ScpiDeserialize for u16 {
     }
 }
 
+/// Three decimal places, as the device formats values.
+impl ScpiSerialize for f32 {
+    fn serialize(&self, out: &mut String) {
+        use std::fmt::Write;
+        write!(out, "{self:.3}").expect("Failed to format number");
+    }
+}
+
+/// Unsigned decimal, e.g. `120.000`.
+impl ScpiDeserialize for f32 {
+    fn deserialize(input: &mut &str) -> crate::Result<Self> {
+        let digits = read_while(input, |c: char| c.is_ascii_digit() || c == '.');
+        digits
+            .parse()
+            .map_err(|_| Error::ResponseDecoding(format!("Number parsing failed: {digits}")))
+    }
+}
+
 #[macro_export]
 macro_rules! impl_scpi_serialize {
     ($type:ty, [ $( $part:tt ),* $(,)? ]) => {
         impl $crate::ScpiSerialize for $type {
             fn serialize(&self, out: &mut String) {
+                self.serialize_styled(out, $crate::MnemonicStyle::Long);
+            }
+
+            fn serialize_styled(&self, out: &mut String, style: $crate::MnemonicStyle) {
                 $(
-                    impl_scpi_serialize!(@part self, out, $part);
+                    impl_scpi_serialize!(@part self, out, style, $part);
                 )*
             }
         }
     };
 
     // Handle string literals
-    (@part $self:ident, $out:ident, $lit:literal) => {
-        $out.push_str($lit);
+    (@part $self:ident, $out:ident, $style:ident, $lit:literal) => {
+        $crate::push_mnemonic($out, $lit, $style);
     };
 
     // Handle field names
-    (@part $self:ident, $out:ident, $field:ident) => {
-        $self.$field.serialize($out);
+    (@part $self:ident, $out:ident, $style:ident, $field:ident) => {
+        $self.$field.serialize_styled($out, $style);
     };
 }
 
@@ -108,6 +250,59 @@ pub fn match_literal(input: &mut &str, literal: &'static str) -> Result<()> {
     }
 }
 
+/// Appends a literal with its mnemonics spelled in `style`, see [`match_mnemonic`] for the forms.
+pub fn push_mnemonic(out: &mut String, literal: &str, style: MnemonicStyle) {
+    match style {
+        MnemonicStyle::Long => out.push_str(literal),
+        MnemonicStyle::Upper => out.push_str(&literal.to_ascii_uppercase()),
+        MnemonicStyle::Short => {
+            let mut rest = literal;
+            while !rest.is_empty() {
+                let long = read_while(&mut rest, |c: char| c.is_ascii_alphabetic());
+                let short = read_while(&mut &*long, |c: char| c.is_ascii_uppercase());
+                out.push_str(if short.is_empty() { long } else { short });
+                out.push_str(read_while(&mut rest, |c: char| !c.is_ascii_alphabetic()));
+            }
+        }
+    }
+}
+
+/// Matches a SCPI mnemonic literal, case-insensitively, in long or short form.
+///
+/// The short form of a keyword is its leading upper case part, e.g. `MEASure` matches
+/// `MEASURE`, `meas` or `Meas`. A space in the literal matches any amount of whitespace.
+pub fn match_mnemonic(input: &mut &str, literal: &'static str) -> Result<()> {
+    let mismatch = || {
+        Error::ResponseDecoding(format!(
+            "Expected mnemonic `{literal}` not matched `{input}`"
+        ))
+    };
+
+    let mut rest = *input;
+    let mut pattern = literal;
+    while let Some(expected) = pattern.chars().next() {
+        if expected.is_ascii_alphabetic() {
+            let long = read_while(&mut pattern, |c: char| c.is_ascii_alphabetic());
+            let short = read_while(&mut &*long, |c: char| c.is_ascii_uppercase());
+            let keyword = read_while(&mut rest, |c: char| c.is_ascii_alphabetic());
+            if !keyword.eq_ignore_ascii_case(long) && !keyword.eq_ignore_ascii_case(short) {
+                return Err(mismatch());
+            }
+        } else if expected == ' ' {
+            pattern = &pattern[1..];
+            if read_while(&mut rest, |c: char| c == ' ' || c == '\t').is_empty() {
+                return Err(mismatch());
+            }
+        } else {
+            pattern = &pattern[expected.len_utf8()..];
+            rest = rest.strip_prefix(expected).ok_or_else(mismatch)?;
+        }
+    }
+
+    *input = rest;
+    Ok(())
+}
+
 pub fn read_until<'a>(input: &mut &'a str, delimiter: char) -> Result<&'a str> {
     if let Some(index) = input.find(delimiter) {
         let (head, tail) = input.split_at(index);
@@ -120,6 +315,38 @@ pub fn read_until<'a>(input: &mut &'a str, delimiter: char) -> Result<&'a str> {
     }
 }
 
+pub fn read_until_literal<'a>(input: &mut &'a str, literal: &'static str) -> Result<&'a str> {
+    if let Some(index) = input.find(literal) {
+        let (head, tail) = input.split_at(index);
+        *input = &tail[literal.len()..];
+        Ok(head)
+    } else {
+        Err(Error::ResponseDecoding(format!(
+            "Expected `{literal}` in `{input}`"
+        )))
+    }
+}
+
+/// Reads up to the first match of `literal` by the rules of [`match_mnemonic`], which is
+/// consumed. Literals starting with a keyword only match at the start of a word.
+pub fn read_until_mnemonic<'a>(input: &mut &'a str, literal: &'static str) -> Result<&'a str> {
+    let keyword = literal.starts_with(|c: char| c.is_ascii_alphabetic());
+    let mut previous = None;
+    for (index, c) in input.char_indices() {
+        let boundary = !keyword || !previous.is_some_and(|p: char| p.is_ascii_alphabetic());
+        let mut rest = &input[index..];
+        if boundary && match_mnemonic(&mut rest, literal).is_ok() {
+            let head = &input[..index];
+            *input = rest;
+            return Ok(head);
+        }
+        previous = Some(c);
+    }
+    Err(Error::ResponseDecoding(format!(
+        "Expected `{literal}` in `{input}`"
+    )))
+}
+
 pub fn read_while<'a, P>(input: &mut &'a str, pattern: P) -> &'a str
 where
     P: Pattern,
@@ -194,9 +421,13 @@ macro_rules! scpi_enum {
 
         impl $crate::ScpiSerialize for $name {
             fn serialize(&self, out: &mut String) {
+                self.serialize_styled(out, $crate::MnemonicStyle::Long);
+            }
+
+            fn serialize_styled(&self, out: &mut String, style: $crate::MnemonicStyle) {
                 match self {
                     $(
-                        Self::$variant => out.push_str($literal),
+                        Self::$variant => $crate::push_mnemonic(out, $literal, style),
                     )*
                 }
             }
@@ -232,6 +463,35 @@ mod tests {
         assert!(check_empty(input).is_ok());
     }
 
+    #[test]
+    fn test_match_mnemonic() {
+        let input = &mut "meas:VOLTAGE?  ch1";
+        assert!(match_mnemonic(input, "MEASure:").is_ok());
+        assert!(match_mnemonic(input, "VOLTage?").is_ok());
+        assert!(match_mnemonic(input, " ").is_ok());
+        assert!(match_mnemonic(input, "CH1").is_ok());
+        assert!(check_empty(input).is_ok());
+
+        assert!(match_mnemonic(&mut "MEA:", "MEASure:").is_err());
+        assert!(match_mnemonic(&mut "MEASURED:", "MEASure:").is_err());
+        assert!(match_mnemonic(&mut "ONE", "ON").is_err());
+    }
+
+    #[test]
+    fn test_push_mnemonic() {
+        let mut out = String::new();
+        push_mnemonic(&mut out, "MEASure:VOLTage? CH1", MnemonicStyle::Short);
+        assert_eq!(out, "MEAS:VOLT? CH1");
+
+        let mut out = String::new();
+        push_mnemonic(&mut out, "*IDN?;IPaddr", MnemonicStyle::Short);
+        assert_eq!(out, "*IDN?;IP");
+
+        let mut out = String::new();
+        push_mnemonic(&mut out, "MEASure:VOLTage?", MnemonicStyle::Upper);
+        assert_eq!(out, "MEASURE:VOLTAGE?");
+    }
+
     #[test]
     fn test_read_until() {
         let input = &mut "12,34";
@@ -240,6 +500,31 @@ mod tests {
         assert!(check_empty(input).is_ok());
     }
 
+    #[test]
+    fn test_read_until_literal() {
+        let input = &mut "12? 34";
+        assert_eq!(read_until_literal(input, "? ").unwrap(), "12");
+        assert!(read_until_literal(input, "? ").is_err());
+        assert!(match_literal(input, "34").is_ok());
+        assert!(check_empty(input).is_ok());
+    }
+
+    #[test]
+    fn test_read_until_mnemonic() {
+        let input = &mut "VOLT  3.3";
+        assert_eq!(read_until_mnemonic(input, " ").unwrap(), "VOLT");
+        assert_eq!(*input, "3.3");
+
+        let input = &mut "CH1:voltage 3.3";
+        assert_eq!(read_until_mnemonic(input, ":VOLTage").unwrap(), "CH1");
+        assert_eq!(*input, " 3.3");
+
+        // Keywords only match whole words.
+        let input = &mut "MON ON";
+        assert_eq!(read_until_mnemonic(input, "ON").unwrap(), "MON ");
+        assert!(read_until_mnemonic(&mut "12", ",").is_err());
+    }
+
     #[test]
     fn test_read_while() {
         let input = &mut "12,34";
@@ -249,6 +534,25 @@ mod tests {
         assert!(check_empty(input).is_ok());
     }
 
+    #[test]
+    fn test_option() {
+        assert_eq!(Option::<u16>::deserialize(&mut "").unwrap(), None);
+        assert_eq!(Option::<u16>::deserialize(&mut "12").unwrap(), Some(12));
+        assert!(Option::<u16>::deserialize(&mut "x").is_err());
+    }
+
+    #[test]
+    fn test_f32() {
+        let mut input = "120.500,1";
+        assert_eq!(f32::deserialize(&mut input).unwrap(), 120.5);
+        assert_eq!(input, ",1");
+        assert!(f32::deserialize(&mut "x").is_err());
+
+        let mut out = String::new();
+        2.5f32.serialize(&mut out);
+        assert_eq!(out, "2.500");
+    }
+
     #[test]
     fn test_read_all() {
         let input = &mut "12,34\nasdf";
@@ -0,0 +1,638 @@
+//! Long-term recording of measurements and status of both channels into rotating files.
+
+use std::{
+    fs::File,
+    future::Future,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    pin::pin,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::time::{MissedTickBehavior, interval, sleep, timeout};
+
+use crate::{
+    Error, Result,
+    commands::{ChannelMode, DisplayMode, IdentityResponse, OperationMode, State},
+    sampling::check_period,
+    snapshot::{ChannelSnapshot, Snapshot},
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Csv,
+    JsonLines,
+    /// Row groups are written at least once per minute, but the footer only when the file is
+    /// finished, on rotation or stop. A file cut short by a crash or kill has no footer and
+    /// cannot be read without repair tools, prefer a short rotation or a line based format.
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl LogFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::JsonLines => "jsonl",
+            #[cfg(feature = "parquet")]
+            LogFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggerConfig {
+    pub directory: PathBuf,
+    /// File names are `<prefix>-<unix millis>.<extension>`.
+    pub prefix: String,
+    pub format: LogFormat,
+    pub period: Duration,
+    /// A new file is started after this duration, and on every (re)connect.
+    pub rotation: Duration,
+    /// Connection attempts and snapshots not completed within this duration are retried.
+    pub timeout: Duration,
+    pub reconnect_delay: Duration,
+    /// Verified on every (re)connect, a mismatch stops the logger.
+    pub serial_number: Option<String>,
+}
+
+impl LoggerConfig {
+    pub fn new(directory: impl Into<PathBuf>, format: LogFormat) -> Self {
+        LoggerConfig {
+            directory: directory.into(),
+            prefix: "spd3303x".to_string(),
+            format,
+            period: Duration::from_secs(1),
+            rotation: Duration::from_secs(24 * 60 * 60),
+            timeout: Duration::from_secs(5),
+            reconnect_delay: Duration::from_secs(5),
+            serial_number: None,
+        }
+    }
+}
+
+/// Records [`Snapshot`]s of a device, reconnecting whenever the connection fails.
+pub struct DataLogger {
+    host: String,
+    config: LoggerConfig,
+}
+
+enum Session {
+    Stopped,
+    ConnectionLost,
+}
+
+impl DataLogger {
+    pub fn new(host: impl Into<String>, config: LoggerConfig) -> Self {
+        DataLogger {
+            host: host.into(),
+            config,
+        }
+    }
+
+    /// Logs until `stop` completes, the current file is finished before returning.
+    /// Only file errors, serial number mismatches and a zero period end the logger early.
+    pub async fn run(&self, stop: impl Future<Output = ()>) -> Result<()> {
+        check_period("Logging period", self.config.period)?;
+        std::fs::create_dir_all(&self.config.directory)?;
+        let mut stop = pin!(stop);
+
+        loop {
+            let connected = tokio::select! {
+                _ = &mut stop => return Ok(()),
+                connected = timeout(self.config.timeout, self.connect()) => connected,
+            };
+
+            if let Ok(Ok((spd, identity))) = connected {
+                match self.session(spd, &identity, &mut stop).await? {
+                    Session::Stopped => return Ok(()),
+                    Session::ConnectionLost => {}
+                }
+            } else if let Ok(Err(error @ Error::SerialMismatch(_))) = connected {
+                return Err(error);
+            }
+
+            tokio::select! {
+                _ = &mut stop => return Ok(()),
+                _ = sleep(self.config.reconnect_delay) => {}
+            }
+        }
+    }
+
+    async fn connect(&self) -> Result<(Spd3303x, IdentityResponse)> {
+        let mut spd = Spd3303x::connect_hostname(&self.host).await?;
+        if let Some(serial_number) = &self.config.serial_number {
+            spd.verify_serial_number(serial_number).await?;
+        }
+        let identity = spd.get_identity().await?;
+        Ok((spd, identity))
+    }
+
+    async fn session(
+        &self,
+        mut spd: Spd3303x,
+        identity: &IdentityResponse,
+        stop: &mut (impl Future<Output = ()> + Unpin),
+    ) -> Result<Session> {
+        let mut ticker = interval(self.config.period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let mut writer = self.create_writer(identity)?;
+        let mut opened = Instant::now();
+
+        let session = loop {
+            tokio::select! {
+                _ = &mut *stop => break Session::Stopped,
+                _ = ticker.tick() => {}
+            }
+
+            let Ok(Ok(snapshot)) = timeout(self.config.timeout, spd.snapshot()).await else {
+                break Session::ConnectionLost;
+            };
+
+            if opened.elapsed() >= self.config.rotation {
+                writer.finish()?;
+                writer = self.create_writer(identity)?;
+                opened = Instant::now();
+            }
+            writer.write(&LogRecord::from(&snapshot))?;
+        };
+
+        writer.finish()?;
+        Ok(session)
+    }
+
+    fn create_writer(&self, identity: &IdentityResponse) -> Result<Box<dyn RecordWriter>> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = self.config.directory.join(format!(
+            "{}-{millis}.{}",
+            self.config.prefix,
+            self.config.format.extension()
+        ));
+        let metadata = metadata(&self.host, identity);
+        create_writer(&path, self.config.format, &metadata)
+    }
+}
+
+fn metadata(host: &str, identity: &IdentityResponse) -> Vec<(&'static str, String)> {
+    vec![
+        ("host", host.to_string()),
+        ("company_name", identity.company_name.clone()),
+        ("model_number", identity.model_number.clone()),
+        ("serial_number", identity.serial_number.clone()),
+        ("software_version", identity.software_version.clone()),
+        ("hardware_version", identity.hardware_version.clone()),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Timestamp,
+    Reading,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// Seconds since the unix epoch.
+    Timestamp(f64),
+    Reading(f32),
+    Text(&'static str),
+}
+
+/// One row of the log, flattened from a [`Snapshot`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub fields: Vec<Field>,
+}
+
+impl LogRecord {
+    pub const COLUMNS: [(&'static str, FieldKind); 20] = [
+        ("timestamp", FieldKind::Timestamp),
+        ("operation_mode", FieldKind::Text),
+        ("ch1_voltage", FieldKind::Reading),
+        ("ch1_current", FieldKind::Reading),
+        ("ch1_power", FieldKind::Reading),
+        ("ch1_voltage_limit", FieldKind::Reading),
+        ("ch1_current_limit", FieldKind::Reading),
+        ("ch1_mode", FieldKind::Text),
+        ("ch1_output", FieldKind::Text),
+        ("ch1_timer", FieldKind::Text),
+        ("ch1_display", FieldKind::Text),
+        ("ch2_voltage", FieldKind::Reading),
+        ("ch2_current", FieldKind::Reading),
+        ("ch2_power", FieldKind::Reading),
+        ("ch2_voltage_limit", FieldKind::Reading),
+        ("ch2_current_limit", FieldKind::Reading),
+        ("ch2_mode", FieldKind::Text),
+        ("ch2_output", FieldKind::Text),
+        ("ch2_timer", FieldKind::Text),
+        ("ch2_display", FieldKind::Text),
+    ];
+
+    pub fn columns(&self) -> impl Iterator<Item = (&'static str, &Field)> {
+        Self::COLUMNS
+            .into_iter()
+            .map(|(name, _)| name)
+            .zip(&self.fields)
+    }
+
+    fn header() -> String {
+        Self::COLUMNS.map(|(name, _)| name).join(",")
+    }
+}
+
+impl From<&Snapshot> for LogRecord {
+    fn from(snapshot: &Snapshot) -> Self {
+        let timestamp = snapshot
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut fields = vec![
+            Field::Timestamp(timestamp),
+            Field::Text(match snapshot.operation_mode {
+                OperationMode::Independent => "independent",
+                OperationMode::Series => "series",
+                OperationMode::Parallel => "parallel",
+            }),
+        ];
+        for channel in [&snapshot.channel_one, &snapshot.channel_two] {
+            fields.extend(channel_fields(channel));
+        }
+        LogRecord { fields }
+    }
+}
+
+fn channel_fields(channel: &ChannelSnapshot) -> [Field; 9] {
+    let state = |state| match state {
+        State::On => "ON",
+        State::Off => "OFF",
+    };
+    [
+        Field::Reading(channel.voltage),
+        Field::Reading(channel.current),
+        Field::Reading(channel.power),
+        Field::Reading(channel.voltage_limit),
+        Field::Reading(channel.current_limit),
+        Field::Text(match channel.mode {
+            ChannelMode::ConstantVoltage => "CV",
+            ChannelMode::ConstantCurrent => "CC",
+        }),
+        Field::Text(state(channel.output)),
+        Field::Text(state(channel.timer)),
+        Field::Text(match channel.display {
+            DisplayMode::DigitalDisplay => "digital",
+            DisplayMode::WaveformDisplay => "waveform",
+        }),
+    ]
+}
+
+/// Readings are millivolt/milliampere resolution, rounding avoids `f32` artifacts in text output.
+fn reading_to_f64(value: f32) -> f64 {
+    (f64::from(value) * 1000.0).round() / 1000.0
+}
+
+pub trait RecordWriter {
+    fn write(&mut self, record: &LogRecord) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Creates the file at `path` and writes the `metadata` header.
+pub fn create_writer(
+    path: &Path,
+    format: LogFormat,
+    metadata: &[(&'static str, String)],
+) -> Result<Box<dyn RecordWriter>> {
+    let file = File::create(path)?;
+    Ok(match format {
+        LogFormat::Csv => Box::new(CsvWriter::new(file, metadata)?),
+        LogFormat::JsonLines => Box::new(JsonLinesWriter::new(file, metadata)?),
+        #[cfg(feature = "parquet")]
+        LogFormat::Parquet => Box::new(parquet_writer::ParquetWriter::new(file, metadata)?),
+    })
+}
+
+/// Metadata as `# key: value` comment lines, followed by the header row.
+struct CsvWriter {
+    out: BufWriter<File>,
+}
+
+impl CsvWriter {
+    fn new(file: File, metadata: &[(&'static str, String)]) -> Result<Self> {
+        let mut out = BufWriter::new(file);
+        for (key, value) in metadata {
+            writeln!(out, "# {key}: {value}")?;
+        }
+        writeln!(out, "{}", LogRecord::header())?;
+        out.flush()?;
+        Ok(CsvWriter { out })
+    }
+}
+
+impl RecordWriter for CsvWriter {
+    fn write(&mut self, record: &LogRecord) -> Result<()> {
+        let row = record
+            .fields
+            .iter()
+            .map(|field| match field {
+                Field::Timestamp(seconds) => format!("{seconds:.3}"),
+                Field::Reading(value) => reading_to_f64(*value).to_string(),
+                Field::Text(text) => text.to_string(),
+            })
+            .collect::<Vec<_>>();
+        writeln!(self.out, "{}", row.join(","))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Metadata as first line `{"metadata":{...}}`, followed by one object per record.
+struct JsonLinesWriter {
+    out: BufWriter<File>,
+}
+
+impl JsonLinesWriter {
+    fn new(file: File, metadata: &[(&'static str, String)]) -> Result<Self> {
+        let mut out = BufWriter::new(file);
+        let metadata = metadata
+            .iter()
+            .map(|(key, value)| (key.to_string(), serde_json::Value::from(value.as_str())))
+            .collect::<serde_json::Map<_, _>>();
+        writeln!(out, "{}", serde_json::json!({ "metadata": metadata }))?;
+        out.flush()?;
+        Ok(JsonLinesWriter { out })
+    }
+}
+
+impl RecordWriter for JsonLinesWriter {
+    fn write(&mut self, record: &LogRecord) -> Result<()> {
+        let object = record
+            .columns()
+            .map(|(column, field)| {
+                let value = match field {
+                    Field::Timestamp(seconds) => serde_json::Value::from(*seconds),
+                    Field::Reading(value) => serde_json::Value::from(reading_to_f64(*value)),
+                    Field::Text(text) => serde_json::Value::from(*text),
+                };
+                (column.to_string(), value)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        writeln!(self.out, "{}", serde_json::Value::Object(object))?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_writer {
+    use std::{
+        fs::File,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use parquet::{
+        basic::{LogicalType, Repetition, Type as PhysicalType},
+        data_type::{ByteArray, ByteArrayType, DoubleType, FloatType},
+        file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+        schema::types::{Type, TypePtr},
+    };
+
+    use super::{Field, FieldKind, LogRecord, RecordWriter};
+    use crate::{Error, Result};
+
+    /// Rows are buffered and written as one row group per [`ROW_GROUP_SIZE`] records or
+    /// [`ROW_GROUP_INTERVAL`], metadata is stored as key-value metadata in the footer.
+    pub(super) struct ParquetWriter {
+        writer: SerializedFileWriter<File>,
+        rows: Vec<LogRecord>,
+        /// When the oldest buffered row was written.
+        buffered_since: Option<Instant>,
+    }
+
+    const ROW_GROUP_SIZE: usize = 1024;
+    const ROW_GROUP_INTERVAL: Duration = Duration::from_secs(60);
+
+    fn parquet_error(error: parquet::errors::ParquetError) -> Error {
+        Error::Other(format!("Parquet: {error}"))
+    }
+
+    fn schema() -> Result<TypePtr> {
+        let fields = LogRecord::COLUMNS
+            .iter()
+            .map(|(name, kind)| {
+                match kind {
+                    FieldKind::Timestamp => {
+                        Type::primitive_type_builder(name, PhysicalType::DOUBLE)
+                    }
+                    FieldKind::Reading => Type::primitive_type_builder(name, PhysicalType::FLOAT),
+                    FieldKind::Text => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                        .with_logical_type(Some(LogicalType::String)),
+                }
+                .with_repetition(Repetition::REQUIRED)
+                .build()
+                .map(Arc::new)
+                .map_err(parquet_error)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Type::group_type_builder("spd3303x")
+            .with_fields(fields)
+            .build()
+            .map(Arc::new)
+            .map_err(parquet_error)
+    }
+
+    impl ParquetWriter {
+        pub(super) fn new(file: File, metadata: &[(&'static str, String)]) -> Result<Self> {
+            let metadata = metadata
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.to_string(), value.clone()))
+                .collect();
+            let properties = WriterProperties::builder()
+                .set_key_value_metadata(Some(metadata))
+                .build();
+            let writer = SerializedFileWriter::new(file, schema()?, Arc::new(properties))
+                .map_err(parquet_error)?;
+            Ok(ParquetWriter {
+                writer,
+                rows: Vec::with_capacity(ROW_GROUP_SIZE),
+                buffered_since: None,
+            })
+        }
+
+        fn flush_rows(&mut self) -> Result<()> {
+            if self.rows.is_empty() {
+                return Ok(());
+            }
+
+            let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+            let mut index = 0;
+            while let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+                let fields = self.rows.iter().map(|row| row.fields[index]);
+                match LogRecord::COLUMNS[index].1 {
+                    FieldKind::Timestamp => {
+                        let values = fields
+                            .map(|field| match field {
+                                Field::Timestamp(value) => value,
+                                _ => f64::NAN,
+                            })
+                            .collect::<Vec<_>>();
+                        column
+                            .typed::<DoubleType>()
+                            .write_batch(&values, None, None)
+                    }
+                    FieldKind::Reading => {
+                        let values = fields
+                            .map(|field| match field {
+                                Field::Reading(value) => value,
+                                _ => f32::NAN,
+                            })
+                            .collect::<Vec<_>>();
+                        column.typed::<FloatType>().write_batch(&values, None, None)
+                    }
+                    FieldKind::Text => {
+                        let values = fields
+                            .map(|field| match field {
+                                Field::Text(text) => ByteArray::from(text),
+                                _ => ByteArray::from(""),
+                            })
+                            .collect::<Vec<_>>();
+                        column
+                            .typed::<ByteArrayType>()
+                            .write_batch(&values, None, None)
+                    }
+                }
+                .map_err(parquet_error)?;
+                column.close().map_err(parquet_error)?;
+                index += 1;
+            }
+            row_group.close().map_err(parquet_error)?;
+            self.rows.clear();
+            self.buffered_since = None;
+            Ok(())
+        }
+    }
+
+    impl RecordWriter for ParquetWriter {
+        fn write(&mut self, record: &LogRecord) -> Result<()> {
+            self.rows.push(record.clone());
+            let buffered_since = *self.buffered_since.get_or_insert_with(Instant::now);
+            if self.rows.len() >= ROW_GROUP_SIZE || buffered_since.elapsed() >= ROW_GROUP_INTERVAL {
+                self.flush_rows()?;
+            }
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> Result<()> {
+            self.flush_rows()?;
+            self.writer.close().map_err(parquet_error)?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Channel;
+
+    fn snapshot() -> Snapshot {
+        let channel = |channel, voltage| ChannelSnapshot {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1500),
+            channel,
+            voltage_limit: 3.3,
+            current_limit: 0.5,
+            voltage,
+            current: 0.1,
+            power: 0.33,
+            mode: ChannelMode::ConstantVoltage,
+            output: State::On,
+            timer: State::Off,
+            display: DisplayMode::DigitalDisplay,
+        };
+        Snapshot {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1500),
+            operation_mode: OperationMode::Independent,
+            channel_one: channel(Channel::One, 3.299),
+            channel_two: channel(Channel::Two, 0.0),
+        }
+    }
+
+    /// Unique per process, tests of concurrent runs do not share files.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("spd3303x-test-{}-{name}", std::process::id()))
+    }
+
+    fn write_log(format: LogFormat, name: &str) -> String {
+        let path = temp_path(name);
+        let metadata = [("serial_number", "SPD00001130025".to_string())];
+        let mut writer = create_writer(&path, format, &metadata).unwrap();
+        writer.write(&LogRecord::from(&snapshot())).unwrap();
+        writer.finish().unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        content
+    }
+
+    #[test]
+    fn test_csv() {
+        let content = write_log(LogFormat::Csv, "log.csv");
+        let mut lines = content.lines();
+        assert_eq!(lines.next(), Some("# serial_number: SPD00001130025"));
+        assert_eq!(lines.next(), Some(LogRecord::header().as_str()));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "1.500,independent,3.299,0.1,0.33,3.3,0.5,CV,ON,OFF,digital,\
+                 0,0.1,0.33,3.3,0.5,CV,ON,OFF,digital"
+            )
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let path = temp_path("log.parquet");
+        let metadata = [("serial_number", "SPD00001130025".to_string())];
+        let mut writer = create_writer(&path, LogFormat::Parquet, &metadata).unwrap();
+        writer.write(&LogRecord::from(&snapshot())).unwrap();
+        writer.write(&LogRecord::from(&snapshot())).unwrap();
+        writer.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let file_metadata = reader.metadata().file_metadata();
+        assert_eq!(file_metadata.num_rows(), 2);
+        let key_values = file_metadata.key_value_metadata().unwrap();
+        assert_eq!(key_values[0].key, "serial_number");
+        assert_eq!(key_values[0].value.as_deref(), Some("SPD00001130025"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let content = write_log(LogFormat::JsonLines, "log.jsonl");
+        let mut lines = content.lines();
+        let metadata: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(metadata["metadata"]["serial_number"], "SPD00001130025");
+        let record: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(record["ch1_voltage"], 3.299);
+        assert_eq!(record["ch2_output"], "ON");
+    }
+}
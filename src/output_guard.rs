@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use tokio::sync::{Mutex, oneshot};
+
+use crate::{
+    Result,
+    commands::{Channel, State},
+    spd3303x::Spd3303x,
+};
+
+/// Keeps the output of a channel on while alive, see [`ChannelControl::enable_guarded`].
+///
+/// Dropping the guard, also while unwinding from a panic or when the owning task is cancelled,
+/// switches the output off from a background cleanup task, as there is no async drop.
+/// The cleanup runs on the tokio runtime, so it is lost if the runtime shuts down right after
+/// the drop, e.g. at the end of `#[tokio::main]`. Use [`OutputGuard::disable`] where possible,
+/// it also reports errors.
+///
+/// [`ChannelControl::enable_guarded`]: crate::channel_control::ChannelControl::enable_guarded
+#[must_use = "the output is switched off again when the guard is dropped"]
+pub struct OutputGuard {
+    channel: Channel,
+    spd: Arc<Mutex<Spd3303x>>,
+    /// Dropped with the guard, which wakes the cleanup task.
+    disabled: oneshot::Sender<()>,
+}
+
+impl OutputGuard {
+    pub(crate) async fn enable(spd: Arc<Mutex<Spd3303x>>, channel: Channel) -> Result<Self> {
+        let (disabled, dropped) = oneshot::channel();
+        // Spawned before switching on, so the output is never on without cleanup.
+        tokio::spawn(cleanup(spd.clone(), channel, dropped));
+
+        // On failure, the sender is dropped and the cleanup switches off, should the output be on.
+        spd.lock()
+            .await
+            .set_output(channel.into(), State::On)
+            .await?;
+        Ok(OutputGuard {
+            channel,
+            spd,
+            disabled,
+        })
+    }
+
+    pub fn get_channel(&self) -> Channel {
+        self.channel
+    }
+
+    /// Switches the output off and releases the guard without cleanup task.
+    pub async fn disable(self) -> Result<()> {
+        let OutputGuard {
+            channel,
+            spd,
+            disabled,
+        } = self;
+        let result = spd
+            .lock()
+            .await
+            .set_output(channel.into(), State::Off)
+            .await;
+        if result.is_ok() {
+            let _ = disabled.send(());
+        }
+        result
+    }
+}
+
+async fn cleanup(spd: Arc<Mutex<Spd3303x>>, channel: Channel, dropped: oneshot::Receiver<()>) {
+    if dropped.await.is_err() {
+        let _ = spd
+            .lock()
+            .await
+            .set_output(channel.into(), State::Off)
+            .await;
+    }
+}
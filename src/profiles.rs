@@ -0,0 +1,396 @@
+//! Parameterized supply robustness profiles: dips, brown-outs, cranking and restarts.
+//!
+//! Profiles run on the device timer if they fit its five groups of whole seconds, otherwise
+//! they are played from the host, see [`Profile::compile`].
+
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+
+use crate::{
+    Error, Result,
+    channel_control::ChannelControl,
+    commands::{LimitQuantity, Reading, State, TimeInterval},
+    timer_program::{MAX_CURRENT, MAX_VOLTAGE, TimerProgram, TimerStep},
+    waveform::{Interpolation, PlaybackReport, Repeat, Waveform},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    /// Jumps to the voltage and holds it.
+    Hold {
+        voltage: Reading,
+        duration: Duration,
+    },
+    /// Ramps linearly from the previous voltage.
+    Ramp {
+        voltage: Reading,
+        duration: Duration,
+    },
+}
+
+impl Segment {
+    pub fn get_voltage(&self) -> Reading {
+        match self {
+            Segment::Hold { voltage, .. } | Segment::Ramp { voltage, .. } => *voltage,
+        }
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        match self {
+            Segment::Hold { duration, .. } | Segment::Ramp { duration, .. } => *duration,
+        }
+    }
+
+    fn with_voltage(self, voltage: Reading) -> Self {
+        match self {
+            Segment::Hold { duration, .. } => Segment::Hold { voltage, duration },
+            Segment::Ramp { duration, .. } => Segment::Ramp { voltage, duration },
+        }
+    }
+}
+
+/// Voltage segments starting from `nominal`, with a constant current limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub nominal: Reading,
+    pub current: Reading,
+    pub segments: Vec<Segment>,
+}
+
+/// How a profile is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompiledProfile {
+    Timer(TimerProgram),
+    Host(Waveform),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub name: String,
+    pub compiled: CompiledProfile,
+    /// Voltage setpoints with their offsets from `started`. As sent for host playback, as
+    /// programmed for timer programs, which the device steps through itself.
+    pub setpoints: Vec<(Duration, Reading)>,
+    /// When the timer was switched on or playback was spawned.
+    pub started: Instant,
+    /// When the timer was stopped or playback finished.
+    pub stopped: Instant,
+    /// Timing of host playback, `None` for timer programs.
+    pub playback: Option<PlaybackReport>,
+}
+
+fn hold(voltage: Reading, duration: Duration) -> Segment {
+    Segment::Hold { voltage, duration }
+}
+
+fn ramp(voltage: Reading, duration: Duration) -> Segment {
+    Segment::Ramp { voltage, duration }
+}
+
+/// `level` as a fraction of `nominal`.
+fn fraction(nominal: Reading, level: f32) -> Reading {
+    Reading::from(f32::from(nominal) * level)
+}
+
+impl Profile {
+    /// Short drop to `level` times nominal, then back to nominal for `recovery`.
+    pub fn dip(
+        nominal: Reading,
+        current: Reading,
+        level: f32,
+        duration: Duration,
+        recovery: Duration,
+    ) -> Self {
+        Profile {
+            name: format!("dip to {:.0} % for {duration:?}", level * 100.0),
+            nominal,
+            current,
+            segments: vec![
+                hold(fraction(nominal, level), duration),
+                hold(nominal, recovery),
+            ],
+        }
+    }
+
+    /// Slow sag to `level` times nominal, held, then a slow recovery to nominal.
+    pub fn brown_out(
+        nominal: Reading,
+        current: Reading,
+        level: f32,
+        fall: Duration,
+        duration: Duration,
+        rise: Duration,
+    ) -> Self {
+        let low = fraction(nominal, level);
+        Profile {
+            name: format!("brown-out to {:.0} %", level * 100.0),
+            nominal,
+            current,
+            segments: vec![ramp(low, fall), hold(low, duration), ramp(nominal, rise)],
+        }
+    }
+
+    /// Automotive style cranking pulse: a sharp drop to 50 % for `crank`, a plateau at 75 %
+    /// for `plateau` while the engine turns, then a ramp back to nominal over `recovery`.
+    pub fn cranking(
+        nominal: Reading,
+        current: Reading,
+        crank: Duration,
+        plateau: Duration,
+        recovery: Duration,
+    ) -> Self {
+        Profile {
+            name: "cranking".to_string(),
+            nominal,
+            current,
+            segments: vec![
+                hold(fraction(nominal, 0.5), crank),
+                hold(fraction(nominal, 0.75), plateau),
+                ramp(nominal, recovery),
+            ],
+        }
+    }
+
+    /// Supply drops to 0 V for `off`, then restarts at nominal for `on`.
+    pub fn restart(nominal: Reading, current: Reading, off: Duration, on: Duration) -> Self {
+        Profile {
+            name: format!("restart after {off:?}"),
+            nominal,
+            current,
+            segments: vec![hold(Reading::from_millis(0), off), hold(nominal, on)],
+        }
+    }
+
+    /// All voltages scaled by the ratio of `nominal` to the current nominal voltage,
+    /// e.g. to run a 24 V profile at 12 V.
+    pub fn scaled(&self, nominal: Reading) -> Self {
+        let factor = f32::from(nominal) / f32::from(self.nominal).max(f32::EPSILON);
+        Profile {
+            name: self.name.clone(),
+            nominal,
+            current: self.current,
+            segments: self
+                .segments
+                .iter()
+                .map(|segment| segment.with_voltage(fraction(segment.get_voltage(), factor)))
+                .collect(),
+        }
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.segments.iter().map(Segment::get_duration).sum()
+    }
+
+    /// Timer program if the profile has up to five holds of whole seconds, otherwise a linear
+    /// waveform starting at nominal. Fails with [`Error::InvalidWaveform`] if a voltage or the
+    /// current exceeds the range of the channel.
+    pub fn compile(&self) -> Result<CompiledProfile> {
+        if self.current.get_millis() > MAX_CURRENT.get_millis() {
+            return Err(Error::InvalidWaveform(format!(
+                "Profile `{}` limits the current to {:.3} A, exceeding {} A",
+                self.name,
+                f32::from(self.current),
+                f32::from(MAX_CURRENT)
+            )));
+        }
+        let voltages = self.segments.iter().map(Segment::get_voltage);
+        if let Some(voltage) = voltages
+            .chain([self.nominal])
+            .find(|voltage| voltage.get_millis() > MAX_VOLTAGE.get_millis())
+        {
+            return Err(Error::InvalidWaveform(format!(
+                "Profile `{}` reaches {:.3} V, exceeding {} V",
+                self.name,
+                f32::from(voltage),
+                f32::from(MAX_VOLTAGE)
+            )));
+        }
+
+        if let Some(program) = self.to_timer_program() {
+            return Ok(CompiledProfile::Timer(program));
+        }
+
+        let mut time = Duration::ZERO;
+        let mut points = vec![(time, self.nominal)];
+        for segment in &self.segments {
+            if let Segment::Hold { voltage, .. } = segment {
+                points.push((time, *voltage));
+            }
+            time += segment.get_duration();
+            points.push((time, segment.get_voltage()));
+        }
+        Ok(CompiledProfile::Host(Waveform::new(
+            LimitQuantity::Voltage,
+            Interpolation::Linear,
+            points,
+        )?))
+    }
+
+    fn to_timer_program(&self) -> Option<TimerProgram> {
+        let steps = self
+            .segments
+            .iter()
+            .map(|segment| {
+                let seconds = segment.get_duration().as_secs();
+                let whole = segment.get_duration().subsec_nanos() == 0;
+                match segment {
+                    Segment::Hold { voltage, .. }
+                        if whole && (1..=u64::from(TimeInterval::MAX)).contains(&seconds) =>
+                    {
+                        Some(TimerStep {
+                            voltage: *voltage,
+                            current: self.current,
+                            time: (seconds as u16).into(),
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        TimerProgram::new(steps).ok()
+    }
+
+    /// Sets the current limit and nominal voltage, switches the output on and applies the
+    /// profile. Returns once the profile completed, leaving the output on.
+    pub async fn run(&self, control: &ChannelControl) -> Result<ProfileReport> {
+        let compiled = self.compile()?;
+        control
+            .set_limit(LimitQuantity::Current, self.current)
+            .await?;
+        control
+            .set_limit(LimitQuantity::Voltage, self.nominal)
+            .await?;
+        control.set_output(State::On).await?;
+
+        let mut started = Instant::now();
+        let (setpoints, playback) = match &compiled {
+            CompiledProfile::Timer(program) => {
+                control.start_timer_program(program).await?;
+                // The timer runs once the program was written and switched on.
+                started = Instant::now();
+                sleep(self.get_duration()).await;
+                control.set_timer(State::Off).await?;
+                (timer_setpoints(program), None)
+            }
+            CompiledProfile::Host(waveform) => {
+                let report = control
+                    .play(waveform.clone(), Repeat::Once)?
+                    .finish()
+                    .await?;
+                if report.aborted {
+                    return Err(Error::Other(format!(
+                        "Playback of profile `{}` was aborted",
+                        self.name
+                    )));
+                }
+                (report.setpoints.clone(), Some(report))
+            }
+        };
+
+        Ok(ProfileReport {
+            name: self.name.clone(),
+            compiled,
+            setpoints,
+            started,
+            stopped: Instant::now(),
+            playback,
+        })
+    }
+}
+
+fn timer_setpoints(program: &TimerProgram) -> Vec<(Duration, Reading)> {
+    let mut time = Duration::ZERO;
+    program
+        .get_steps()
+        .iter()
+        .map(|step| {
+            let setpoint = (time, step.voltage);
+            time += Duration::from_secs(step.time.get_seconds().into());
+            setpoint
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_compile_to_timer() {
+        let profile = Profile::restart(12.0.into(), 1.0.into(), millis(2000), millis(5000));
+        let CompiledProfile::Timer(program) = profile.compile().unwrap() else {
+            panic!("Expected a timer program");
+        };
+        assert_eq!(program.get_steps().len(), 2);
+        assert_eq!(
+            timer_setpoints(&program),
+            vec![
+                (millis(0), Reading::from(0.0)),
+                (millis(2000), Reading::from(12.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_to_host() {
+        let profile = Profile::dip(12.0.into(), 1.0.into(), 0.5, millis(100), millis(200));
+        let CompiledProfile::Host(waveform) = profile.compile().unwrap() else {
+            panic!("Expected a waveform");
+        };
+        let points = [(0, 12.0), (0, 6.0), (100, 6.0), (100, 12.0), (300, 12.0)]
+            .map(|(time, voltage)| (millis(time), Reading::from(voltage)))
+            .to_vec();
+        assert_eq!(waveform.points, points);
+
+        let brown_out = Profile::brown_out(
+            5.0.into(),
+            1.0.into(),
+            0.8,
+            millis(1000),
+            millis(1000),
+            millis(1000),
+        );
+        assert!(matches!(
+            brown_out.compile().unwrap(),
+            CompiledProfile::Host(_)
+        ));
+    }
+
+    #[test]
+    fn test_scaled() {
+        let profile = Profile::cranking(
+            24.0.into(),
+            2.0.into(),
+            millis(50),
+            millis(500),
+            millis(1000),
+        );
+        let scaled = profile.scaled(12.0.into());
+        assert_eq!(scaled.nominal, Reading::from(12.0));
+        let voltages = scaled
+            .segments
+            .iter()
+            .map(Segment::get_voltage)
+            .collect::<Vec<_>>();
+        assert_eq!(voltages, [6.0, 9.0, 12.0].map(Reading::from).to_vec());
+        assert_eq!(scaled.get_duration(), millis(1550));
+
+        let too_high = profile.scaled(48.0.into());
+        assert!(too_high.compile().is_err());
+
+        let too_much_current = Profile {
+            current: 4.0.into(),
+            ..profile
+        };
+        assert!(matches!(
+            too_much_current.compile(),
+            Err(Error::InvalidWaveform(_))
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+//! Software over-voltage, over-current and over-power protection, see [`ChannelControl::protect`].
+//!
+//! [`ChannelControl::protect`]: crate::channel_control::ChannelControl::protect
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures_util::StreamExt;
+use tokio::{
+    sync::{Mutex, watch},
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, Quantity, State},
+    sampling::{self, Sample, check_period},
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtectionConfig {
+    pub over_voltage: Option<f32>,
+    pub over_current: Option<f32>,
+    pub over_power: Option<f32>,
+    /// How long a threshold must be exceeded before the output is switched off.
+    pub duration: Duration,
+    pub period: Duration,
+}
+
+impl ProtectionConfig {
+    pub fn new() -> Self {
+        ProtectionConfig {
+            over_voltage: None,
+            over_current: None,
+            over_power: None,
+            duration: Duration::ZERO,
+            period: Duration::from_millis(100),
+        }
+    }
+
+    /// Fails with [`Error::InvalidConfig`] for a zero period or without any threshold.
+    pub fn validate(&self) -> Result<()> {
+        check_period("Protection period", self.period)?;
+        if self.thresholds().next().is_none() {
+            return Err(Error::InvalidConfig(
+                "Protection needs at least one threshold".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn thresholds(&self) -> impl Iterator<Item = (Quantity, f32)> {
+        [
+            (Quantity::Voltage, self.over_voltage),
+            (Quantity::Current, self.over_current),
+            (Quantity::Power, self.over_power),
+        ]
+        .into_iter()
+        .filter_map(|(quantity, threshold)| threshold.map(|threshold| (quantity, threshold)))
+    }
+}
+
+impl Default for ProtectionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A tripped protection, the output was switched off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fault {
+    pub channel: Channel,
+    pub quantity: Quantity,
+    pub threshold: f32,
+    /// Measurement that tripped the protection.
+    pub value: f32,
+    pub timestamp: SystemTime,
+}
+
+/// Measurement counts of a [`ProtectionSupervisor`], failing measurements mean the channel
+/// is not supervised.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SupervisorHealth {
+    pub measurements: u64,
+    pub errors: u64,
+    /// Errors since the last successful measurement.
+    pub consecutive_errors: u64,
+    pub last_error: Option<String>,
+}
+
+/// Tracks since when each threshold is exceeded.
+struct Detector {
+    thresholds: Vec<(Quantity, f32, Option<Instant>)>,
+    duration: Duration,
+}
+
+impl Detector {
+    fn new(config: &ProtectionConfig) -> Self {
+        Detector {
+            thresholds: config
+                .thresholds()
+                .map(|(quantity, threshold)| (quantity, threshold, None))
+                .collect(),
+            duration: config.duration,
+        }
+    }
+
+    fn check(&mut self, sample: &Sample) -> Option<Fault> {
+        for (quantity, threshold, exceeded_since) in &mut self.thresholds {
+            let Some(value) = sample.get(*quantity) else {
+                continue;
+            };
+            if value <= *threshold {
+                *exceeded_since = None;
+                continue;
+            }
+
+            let since = exceeded_since.get_or_insert(sample.instant);
+            if sample.instant.saturating_duration_since(*since) >= self.duration {
+                return Some(Fault {
+                    channel: sample.channel,
+                    quantity: *quantity,
+                    threshold: *threshold,
+                    value,
+                    timestamp: sample.timestamp,
+                });
+            }
+        }
+        None
+    }
+
+    fn reset(&mut self) {
+        for (_, _, exceeded_since) in &mut self.thresholds {
+            *exceeded_since = None;
+        }
+    }
+}
+
+/// Watches the measurements of one channel in a background task and switches the output off
+/// once a threshold is exceeded for the configured duration.
+///
+/// The fault is latched: until [`ProtectionSupervisor::reset`] the output is switched off
+/// again whenever it is turned on. Measurement errors do not trip the protection, they are
+/// counted in [`ProtectionSupervisor::get_health`] and supervision resumes with the next
+/// successful measurement. Supervision stops when this is dropped, after the pending request
+/// was answered.
+pub struct ProtectionSupervisor {
+    fault: watch::Sender<Option<Fault>>,
+    health: watch::Sender<SupervisorHealth>,
+    /// Closed on drop, never sent on.
+    _stop: watch::Sender<()>,
+}
+
+impl ProtectionSupervisor {
+    /// Fails if `config` is invalid, see [`ProtectionConfig::validate`].
+    pub fn spawn(
+        spd: Arc<Mutex<Spd3303x>>,
+        channel: Channel,
+        config: ProtectionConfig,
+    ) -> Result<Self> {
+        config.validate()?;
+        let (fault, _) = watch::channel(None);
+        let (health, _) = watch::channel(SupervisorHealth::default());
+        let (stop, stopped) = watch::channel(());
+        tokio::spawn(supervise(
+            spd,
+            channel,
+            config,
+            fault.clone(),
+            health.clone(),
+            stopped,
+        ));
+        Ok(ProtectionSupervisor {
+            fault,
+            health,
+            _stop: stop,
+        })
+    }
+
+    /// The latched fault, if the protection tripped.
+    pub fn get_fault(&self) -> Option<Fault> {
+        *self.fault.borrow()
+    }
+
+    /// Notifies about tripped faults and resets.
+    pub fn subscribe(&self) -> watch::Receiver<Option<Fault>> {
+        self.fault.subscribe()
+    }
+
+    /// Clears the latched fault, the output stays off until switched on again.
+    pub fn reset(&self) {
+        self.fault.send_replace(None);
+    }
+
+    pub fn get_health(&self) -> SupervisorHealth {
+        self.health.borrow().clone()
+    }
+
+    /// Notifies after each measurement.
+    pub fn subscribe_health(&self) -> watch::Receiver<SupervisorHealth> {
+        self.health.subscribe()
+    }
+}
+
+async fn supervise(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: ProtectionConfig,
+    fault: watch::Sender<Option<Fault>>,
+    health: watch::Sender<SupervisorHealth>,
+    mut stopped: watch::Receiver<()>,
+) {
+    let quantities = config
+        .thresholds()
+        .map(|(quantity, _)| quantity)
+        .collect::<Vec<_>>();
+    let mut detector = Detector::new(&config);
+    let mut ticker = interval(config.period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        let mut latched = fault.subscribe();
+        if latched.borrow_and_update().is_some() {
+            // Enforce the latch until reset.
+            tokio::select! {
+                _ = stopped.changed() => return,
+                _ = latched.changed() => {}
+                _ = ticker.tick() => {
+                    let mut spd = spd.lock().await;
+                    if spd.get_output(channel).await.is_ok_and(|state| state == State::On) {
+                        let _ = spd.set_output(channel.into(), State::Off).await;
+                    }
+                }
+            }
+            continue;
+        }
+
+        detector.reset();
+        let measurements = sampling::measurements(spd.clone(), channel, config.period, &quantities);
+        let mut measurements = std::pin::pin!(measurements);
+        let tripped = loop {
+            // Not selected against the next sample, aborting its request would leave the
+            // response unread.
+            if stopped.has_changed().is_err() {
+                return;
+            }
+            let Some(sample) = measurements.next().await else {
+                return;
+            };
+            health.send_modify(|health| match &sample {
+                Ok(_) => {
+                    health.measurements += 1;
+                    health.consecutive_errors = 0;
+                }
+                Err(error) => {
+                    health.errors += 1;
+                    health.consecutive_errors += 1;
+                    health.last_error = Some(error.to_string());
+                }
+            });
+            if let Some(tripped) = sample.ok().and_then(|sample| detector.check(&sample)) {
+                break tripped;
+            }
+        };
+
+        // Switching off is retried with the latch enforcement, should this fail.
+        let _ = spd
+            .lock()
+            .await
+            .set_output(channel.into(), State::Off)
+            .await;
+        fault.send_replace(Some(tripped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(instant: Instant, voltage: f32, current: f32) -> Sample {
+        Sample {
+            channel: Channel::One,
+            instant,
+            timestamp: SystemTime::now(),
+            latency: Duration::ZERO,
+            missed: 0,
+            voltage: Some(voltage),
+            current: Some(current),
+            power: None,
+        }
+    }
+
+    #[test]
+    fn test_detector() {
+        let config = ProtectionConfig {
+            over_current: Some(1.0),
+            duration: Duration::from_millis(200),
+            ..ProtectionConfig::new()
+        };
+        let mut detector = Detector::new(&config);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        assert!(detector.check(&sample(at(0), 5.0, 0.5)).is_none());
+        assert!(detector.check(&sample(at(100), 5.0, 1.5)).is_none());
+        // Dropping below the threshold restarts the duration.
+        assert!(detector.check(&sample(at(200), 5.0, 0.5)).is_none());
+        assert!(detector.check(&sample(at(300), 5.0, 1.5)).is_none());
+        assert!(detector.check(&sample(at(400), 5.0, 1.5)).is_none());
+
+        let fault = detector.check(&sample(at(500), 5.0, 2.0)).unwrap();
+        assert_eq!(fault.quantity, Quantity::Current);
+        assert_eq!(fault.threshold, 1.0);
+        assert_eq!(fault.value, 2.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(matches!(
+            ProtectionConfig::new().validate(),
+            Err(Error::InvalidConfig(_))
+        ));
+        let config = ProtectionConfig {
+            over_current: Some(1.0),
+            ..ProtectionConfig::new()
+        };
+        assert!(config.validate().is_ok());
+        let zero_period = ProtectionConfig {
+            period: Duration::ZERO,
+            ..config
+        };
+        assert!(zero_period.validate().is_err());
+    }
+
+    #[test]
+    fn test_detector_immediate() {
+        let config = ProtectionConfig {
+            over_voltage: Some(5.5),
+            ..ProtectionConfig::new()
+        };
+        let mut detector = Detector::new(&config);
+        let fault = detector.check(&sample(Instant::now(), 6.0, 0.0)).unwrap();
+        assert_eq!(fault.quantity, Quantity::Voltage);
+    }
+}
@@ -0,0 +1,144 @@
+//! Soft-start and soft-stop voltage ramps, see [`ChannelControl::ramp_to`].
+//!
+//! [`ChannelControl::ramp_to`]: crate::channel_control::ChannelControl::ramp_to
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::interval};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, ChannelMode, LimitQuantity, Reading, State},
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RampConfig {
+    pub duration: Duration,
+    pub steps: u32,
+    /// Sets 0 V and enables the output before ramping up from there.
+    pub start_at_zero: bool,
+    /// Stops ramping with [`Error::RampAborted`] once the channel is in constant current mode,
+    /// leaving the setpoint of the last step.
+    pub abort_on_constant_current: bool,
+}
+
+impl RampConfig {
+    pub fn new(duration: Duration, steps: u32) -> Self {
+        RampConfig {
+            duration,
+            steps,
+            start_at_zero: false,
+            abort_on_constant_current: true,
+        }
+    }
+}
+
+/// Evenly spaced setpoints after `start`, ending exactly at `target`.
+pub fn ramp_setpoints(start: Reading, target: Reading, steps: u32) -> Vec<Reading> {
+    let steps = steps.max(1);
+    let start = i64::from(start.get_millis());
+    let target = i64::from(target.get_millis());
+    (1..=i64::from(steps))
+        .map(|step| {
+            let millis = start + (target - start) * step / i64::from(steps);
+            Reading::from_millis(millis as u16)
+        })
+        .collect()
+}
+
+/// Steps the voltage setpoint of `channel` to `target`, one step per `duration / steps`.
+/// Steps shorter than the timer resolution are sent without waiting. Fails with
+/// [`Error::SafetyLimit`] before the first step if `target` exceeds the safety limits.
+pub async fn ramp(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    target: Reading,
+    config: &RampConfig,
+) -> Result<()> {
+    let start = if config.start_at_zero {
+        let mut spd = spd.lock().await;
+        spd.get_safety_limits(channel)
+            .check(channel, LimitQuantity::Voltage, target)?;
+        spd.set_limit(channel, LimitQuantity::Voltage, Reading::from_millis(0))
+            .await?;
+        spd.set_output(channel.into(), State::On).await?;
+        Reading::from_millis(0)
+    } else {
+        let mut spd = spd.lock().await;
+        spd.get_safety_limits(channel)
+            .check(channel, LimitQuantity::Voltage, target)?;
+        spd.get_limit(channel, LimitQuantity::Voltage).await?.into()
+    };
+
+    let setpoints = ramp_setpoints(start, target, config.steps);
+    let period = config.duration / config.steps.max(1);
+    let mut ticker = (!period.is_zero()).then(|| interval(period));
+    if let Some(ticker) = &mut ticker {
+        // The first tick completes immediately, steps start one period after the start.
+        ticker.tick().await;
+    }
+
+    for setpoint in setpoints {
+        if let Some(ticker) = &mut ticker {
+            ticker.tick().await;
+        }
+        // The connection is locked per step only, other commands interleave fairly.
+        let mut spd = spd.lock().await;
+        spd.set_limit(channel, LimitQuantity::Voltage, setpoint)
+            .await?;
+
+        if config.abort_on_constant_current {
+            let status = spd.get_status().await?;
+            if status.get(channel).mode == ChannelMode::ConstantCurrent {
+                return Err(Error::RampAborted(format!(
+                    "Channel {channel:?} entered constant current mode at {:.3} V",
+                    f32::from(setpoint)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ramps the voltage setpoint of `channel` down to zero, then switches the output off.
+/// The setpoint is left at zero. The output is switched off even if the ramp fails, the error of
+/// the ramp takes precedence.
+pub async fn ramp_down_and_off(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    duration: Duration,
+    steps: u32,
+) -> Result<()> {
+    let config = RampConfig {
+        abort_on_constant_current: false,
+        ..RampConfig::new(duration, steps)
+    };
+    let ramped = ramp(spd.clone(), channel, Reading::from_millis(0), &config).await;
+    let switched = spd
+        .lock()
+        .await
+        .set_output(channel.into(), State::Off)
+        .await;
+    ramped.and(switched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ramp_setpoints() {
+        let setpoints = ramp_setpoints(Reading::from(1.0), Reading::from(3.3), 4);
+        assert_eq!(
+            setpoints,
+            [1575, 2150, 2725, 3300].map(Reading::from_millis).to_vec()
+        );
+
+        let setpoints = ramp_setpoints(Reading::from(5.0), Reading::from(0.0), 2);
+        assert_eq!(setpoints, [2500, 0].map(Reading::from_millis).to_vec());
+
+        let setpoints = ramp_setpoints(Reading::from(0.0), Reading::from(5.0), 0);
+        assert_eq!(setpoints, vec![Reading::from(5.0)]);
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    Error, Result,
+    commands::{Channel, LimitQuantity, Reading},
+};
+
+/// Hard caps for the setpoints of one channel, checked on the host before a request is sent,
+/// see [`Spd3303x::set_safety_limits`].
+///
+/// [`Spd3303x::set_safety_limits`]: crate::spd3303x::Spd3303x::set_safety_limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SafetyLimits {
+    pub max_voltage: Option<Reading>,
+    pub max_current: Option<Reading>,
+}
+
+impl SafetyLimits {
+    pub fn new(max_voltage: Reading, max_current: Reading) -> Self {
+        SafetyLimits {
+            max_voltage: Some(max_voltage),
+            max_current: Some(max_current),
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.max_voltage.is_none() && self.max_current.is_none()
+    }
+
+    pub fn check(&self, channel: Channel, quantity: LimitQuantity, value: Reading) -> Result<()> {
+        let max = match quantity {
+            LimitQuantity::Voltage => self.max_voltage,
+            LimitQuantity::Current => self.max_current,
+        };
+        match max {
+            Some(max) if value.get_millis() > max.get_millis() => Err(Error::SafetyLimit(format!(
+                "{quantity:?} of {:.3} exceeds the maximum of {:.3} for channel {channel:?}",
+                f32::from(value),
+                f32::from(max)
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Setpoints written by a request, see [`ScpiRequest::get_setpoints`].
+///
+/// [`ScpiRequest::get_setpoints`]: crate::ScpiRequest::get_setpoints
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Setpoints {
+    #[default]
+    Unchanged,
+    /// Values per channel, a channel of `None` sets the selected one.
+    Values(Vec<(Option<Channel>, LimitQuantity, Reading)>),
+    /// Setpoints only known once the request was sent, e.g. by a recall.
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limits = SafetyLimits::new(Reading::from(3.6), Reading::from(0.5));
+        let check = |quantity, value: f32| limits.check(Channel::One, quantity, value.into());
+
+        assert!(check(LimitQuantity::Voltage, 3.3).is_ok());
+        assert!(check(LimitQuantity::Voltage, 3.6).is_ok());
+        assert!(matches!(
+            check(LimitQuantity::Voltage, 33.0),
+            Err(Error::SafetyLimit(_))
+        ));
+        assert!(check(LimitQuantity::Current, 0.501).is_err());
+
+        let unlimited = SafetyLimits::default();
+        assert!(unlimited.is_unlimited());
+        assert!(
+            unlimited
+                .check(Channel::Two, LimitQuantity::Voltage, Reading::from(32.0))
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use futures_util::{Stream, stream};
+use tokio::{
+    sync::Mutex,
+    time::{Interval, MissedTickBehavior, interval},
+};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, Quantity},
+    spd3303x::Spd3303x,
+};
+
+/// Measurement of one channel, see [`ChannelControl::measurements`].
+///
+/// [`ChannelControl::measurements`]: crate::channel_control::ChannelControl::measurements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub channel: Channel,
+    /// Estimated moment of measurement, halfway through the request round-trip.
+    pub instant: Instant,
+    /// Wall-clock time corresponding to `instant`.
+    pub timestamp: SystemTime,
+    /// Round-trip time of the request, excluding time waiting for the connection.
+    pub latency: Duration,
+    /// Sampling deadlines missed since the previous sample.
+    pub missed: u32,
+    pub voltage: Option<f32>,
+    pub current: Option<f32>,
+    pub power: Option<f32>,
+}
+
+impl Sample {
+    pub fn get(&self, quantity: Quantity) -> Option<f32> {
+        match quantity {
+            Quantity::Voltage => self.voltage,
+            Quantity::Current => self.current,
+            Quantity::Power => self.power,
+        }
+    }
+}
+
+struct Sampler {
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    quantities: Vec<Quantity>,
+    period: Duration,
+    /// Created on the first poll, `interval` requires a runtime.
+    ticker: Option<Interval>,
+    previous_tick: Option<tokio::time::Instant>,
+}
+
+impl Sampler {
+    async fn next(&mut self) -> Result<Sample> {
+        let period = self.period;
+        let ticker = self.ticker.get_or_insert_with(|| {
+            let mut ticker = interval(period);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            ticker
+        });
+        let tick = ticker.tick().await;
+        let missed = self
+            .previous_tick
+            .replace(tick)
+            .map(|previous| missed_deadlines(tick - previous, period))
+            .unwrap_or(0);
+
+        // The connection is locked per sample only, other commands interleave fairly.
+        let mut spd = self.spd.lock().await;
+        let sent = Instant::now();
+        let sent_timestamp = SystemTime::now();
+        let values = spd.measure_each(self.channel, &self.quantities).await?;
+        drop(spd);
+        let latency = sent.elapsed();
+
+        let mut sample = Sample {
+            channel: self.channel,
+            instant: sent + latency / 2,
+            timestamp: sent_timestamp + latency / 2,
+            latency,
+            missed,
+            voltage: None,
+            current: None,
+            power: None,
+        };
+        for (quantity, value) in self.quantities.iter().zip(values) {
+            match quantity {
+                Quantity::Voltage => sample.voltage = Some(value),
+                Quantity::Current => sample.current = Some(value),
+                Quantity::Power => sample.power = Some(value),
+            }
+        }
+        Ok(sample)
+    }
+}
+
+/// Fails with [`Error::InvalidConfig`] for a zero period, which timers do not accept.
+pub(crate) fn check_period(name: &str, period: Duration) -> Result<()> {
+    if period.is_zero() {
+        return Err(Error::InvalidConfig(format!(
+            "{name} must be greater than zero"
+        )));
+    }
+    Ok(())
+}
+
+fn missed_deadlines(elapsed: Duration, period: Duration) -> u32 {
+    let periods = (elapsed.as_secs_f64() / period.as_secs_f64()).round() as u32;
+    periods.saturating_sub(1)
+}
+
+/// Polls `quantities` of `channel` every `period`, the first sample is taken immediately.
+/// Deadlines that pass while a request is still pending are skipped and reported in
+/// [`Sample::missed`]. A zero `period` yields a single [`Error::InvalidConfig`].
+pub fn measurements(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    period: Duration,
+    quantities: &[Quantity],
+) -> impl Stream<Item = Result<Sample>> + use<> {
+    let sampler = check_period("Sampling period", period).map(|_| Sampler {
+        spd,
+        channel,
+        quantities: quantities.to_vec(),
+        period,
+        ticker: None,
+        previous_tick: None,
+    });
+
+    stream::unfold(Some(sampler), |sampler| async move {
+        match sampler? {
+            Ok(mut sampler) => {
+                let sample = sampler.next().await;
+                Some((sample, Some(Ok(sampler))))
+            }
+            Err(error) => Some((Err(error), None)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missed_deadlines() {
+        let period = Duration::from_millis(100);
+        assert_eq!(missed_deadlines(Duration::from_millis(100), period), 0);
+        assert_eq!(missed_deadlines(Duration::from_millis(101), period), 0);
+        assert_eq!(missed_deadlines(Duration::from_millis(300), period), 2);
+    }
+
+    #[test]
+    fn test_check_period() {
+        assert!(check_period("Period", Duration::ZERO).is_err());
+        assert!(check_period("Period", Duration::from_millis(1)).is_ok());
+    }
+}
@@ -0,0 +1,168 @@
+//! Power-up and power-down sequences over several rails, possibly on several supplies.
+
+use std::time::Duration;
+
+use tokio::time::{Instant, sleep};
+
+use crate::{
+    Error, Result,
+    channel_control::ChannelControl,
+    commands::{LimitQuantity, Quantity, Reading, State},
+    fixed_channel_control::FixedChannelControl,
+};
+
+#[derive(Clone, Copy)]
+pub enum Rail<'a> {
+    Channel(&'a ChannelControl),
+    /// CH3 can only be switched, setpoints and readiness do not apply.
+    Fixed(&'a FixedChannelControl),
+}
+
+impl Rail<'_> {
+    async fn set_output(&self, state: State) -> Result<()> {
+        match self {
+            Rail::Channel(control) => control.set_output(state).await,
+            Rail::Fixed(control) => control.set_output(state).await,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Rail::Channel(control) => format!("{:?}", control.get_channel()),
+            Rail::Fixed(control) => format!("{:?}", control.get_channel()),
+        }
+    }
+}
+
+/// Measured voltage within `tolerance` of the voltage setpoint, in volt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Readiness {
+    pub tolerance: f32,
+    pub timeout: Duration,
+    pub period: Duration,
+}
+
+impl Readiness {
+    pub fn new(tolerance: f32, timeout: Duration) -> Self {
+        Readiness {
+            tolerance,
+            timeout,
+            period: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Step<'a> {
+    pub rail: Rail<'a>,
+    pub voltage: Option<Reading>,
+    pub current: Option<Reading>,
+    /// Waited between the previous step and this one, in both directions.
+    pub delay: Duration,
+    pub ready: Option<Readiness>,
+}
+
+impl<'a> Step<'a> {
+    pub fn channel(control: &'a ChannelControl, voltage: Reading) -> Self {
+        Step {
+            rail: Rail::Channel(control),
+            voltage: Some(voltage),
+            current: None,
+            delay: Duration::ZERO,
+            ready: None,
+        }
+    }
+
+    pub fn fixed(control: &'a FixedChannelControl) -> Self {
+        Step {
+            rail: Rail::Fixed(control),
+            voltage: None,
+            current: None,
+            delay: Duration::ZERO,
+            ready: None,
+        }
+    }
+
+    async fn power_up(&self) -> Result<()> {
+        if let Rail::Channel(control) = self.rail {
+            if let Some(current) = self.current {
+                control.set_limit(LimitQuantity::Current, current).await?;
+            }
+            if let Some(voltage) = self.voltage {
+                control.set_limit(LimitQuantity::Voltage, voltage).await?;
+            }
+        }
+        self.rail.set_output(State::On).await?;
+
+        match (self.rail, self.ready) {
+            (Rail::Channel(control), Some(ready)) => self.wait_ready(control, &ready).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn wait_ready(&self, control: &ChannelControl, ready: &Readiness) -> Result<()> {
+        let target = match self.voltage {
+            Some(voltage) => f32::from(voltage),
+            None => control.get_limit(LimitQuantity::Voltage).await?,
+        };
+        let deadline = Instant::now() + ready.timeout;
+        loop {
+            let measured = control.measure(Quantity::Voltage).await?;
+            if (measured - target).abs() <= ready.tolerance {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::SequenceFailed(format!(
+                    "Channel {} not ready within {:?}, measured {measured:.3} V of {target:.3} V",
+                    self.rail.describe(),
+                    ready.timeout
+                )));
+            }
+            sleep(ready.period).await;
+        }
+    }
+}
+
+/// Steps are powered up in order and down in reverse order.
+pub struct Sequence<'a> {
+    pub steps: Vec<Step<'a>>,
+}
+
+impl<'a> Sequence<'a> {
+    pub fn new(steps: Vec<Step<'a>>) -> Self {
+        Sequence { steps }
+    }
+
+    /// Powers up step by step. If a step fails, the rails powered so far, including the failing
+    /// one, are switched off in reverse order and the error of the step is returned.
+    pub async fn power_up(&self) -> Result<()> {
+        for (index, step) in self.steps.iter().enumerate() {
+            sleep(step.delay).await;
+            if let Err(error) = step.power_up().await {
+                let _ = self.switch_off(&self.steps[..=index], false).await;
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches the rails off in reverse order. All rails are attempted even if one fails,
+    /// the first error is returned.
+    pub async fn power_down(&self) -> Result<()> {
+        self.switch_off(&self.steps, true).await
+    }
+
+    async fn switch_off(&self, steps: &[Step<'_>], delayed: bool) -> Result<()> {
+        let mut result = Ok(());
+        let mut delay = Duration::ZERO;
+        for step in steps.iter().rev() {
+            if delayed {
+                sleep(delay).await;
+            }
+            let switched = step.rail.set_output(State::Off).await;
+            result = result.and(switched);
+            delay = step.delay;
+        }
+        result
+    }
+}
@@ -0,0 +1,154 @@
+//! Waiting for measurements and status to reach a condition, see [`ChannelControl::wait_until`].
+//!
+//! [`ChannelControl::wait_until`]: crate::channel_control::ChannelControl::wait_until
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::Mutex,
+    time::{Instant, MissedTickBehavior, interval},
+};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, ChannelMode, LimitQuantity, Reading},
+    snapshot::ChannelSnapshot,
+    spd3303x::Spd3303x,
+};
+
+/// Time between two polls of the channel.
+pub const POLL_PERIOD: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settled {
+    /// Time from the start of waiting until the condition held.
+    pub elapsed: Duration,
+    /// The first reading satisfying the condition.
+    pub snapshot: ChannelSnapshot,
+}
+
+/// Polls snapshots of `channel` until `predicate` holds, or fails with [`Error::Timeout`].
+/// The channel is polled at least once, even with a zero `timeout`.
+pub async fn wait_until(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    mut predicate: impl FnMut(&ChannelSnapshot) -> bool,
+    timeout: Duration,
+) -> Result<Settled> {
+    let start = Instant::now();
+    let mut ticker = interval(POLL_PERIOD);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let snapshot = spd.lock().await.channel_snapshot(channel).await?;
+        let elapsed = start.elapsed();
+        if predicate(&snapshot) {
+            return Ok(Settled { elapsed, snapshot });
+        }
+        if elapsed >= timeout {
+            return Err(Error::Timeout(format!(
+                "Channel {channel:?} did not reach the condition within {timeout:?}, \
+                 last measured {:.3} V, {:.3} A",
+                snapshot.voltage, snapshot.current
+            )));
+        }
+    }
+}
+
+/// Whether the measurement corresponding to `quantity` is within `tolerance` of `value`.
+pub fn within(
+    snapshot: &ChannelSnapshot,
+    quantity: LimitQuantity,
+    value: Reading,
+    tolerance: f32,
+) -> bool {
+    let measured = match quantity {
+        LimitQuantity::Voltage => snapshot.voltage,
+        LimitQuantity::Current => snapshot.current,
+    };
+    (measured - f32::from(value)).abs() <= tolerance
+}
+
+/// Sets the limit, then waits until the corresponding measurement is within `tolerance`.
+///
+/// Current only settles to its limit in constant current mode, e.g. with a load drawing more.
+pub async fn set_and_settle(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    quantity: LimitQuantity,
+    value: Reading,
+    tolerance: f32,
+    timeout: Duration,
+) -> Result<Settled> {
+    spd.lock().await.set_limit(channel, quantity, value).await?;
+    wait_until(
+        spd,
+        channel,
+        |snapshot| within(snapshot, quantity, value, tolerance),
+        timeout,
+    )
+    .await
+}
+
+/// Sets the voltage limit, then waits until the voltage is within `tolerance` or the channel is
+/// in constant current mode. Returns the snapshot and whether it settled within `timeout`.
+pub async fn set_voltage_and_settle(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    voltage: Reading,
+    tolerance: f32,
+    timeout: Duration,
+) -> Result<(ChannelSnapshot, bool)> {
+    spd.lock()
+        .await
+        .set_limit(channel, LimitQuantity::Voltage, voltage)
+        .await?;
+    let settled = wait_until(
+        spd.clone(),
+        channel,
+        |snapshot| {
+            snapshot.mode == ChannelMode::ConstantCurrent
+                || within(snapshot, LimitQuantity::Voltage, voltage, tolerance)
+        },
+        timeout,
+    )
+    .await;
+    match settled {
+        Ok(settled) => Ok((settled.snapshot, true)),
+        Err(Error::Timeout(_)) => {
+            let snapshot = spd.lock().await.channel_snapshot(channel).await?;
+            Ok((snapshot, false))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::commands::{DisplayMode, State};
+
+    #[test]
+    fn test_within() {
+        let snapshot = ChannelSnapshot {
+            timestamp: SystemTime::now(),
+            channel: Channel::One,
+            voltage_limit: 3.3,
+            current_limit: 1.0,
+            voltage: 3.28,
+            current: 0.2,
+            power: 0.656,
+            mode: ChannelMode::ConstantVoltage,
+            output: State::On,
+            timer: State::Off,
+            display: DisplayMode::DigitalDisplay,
+        };
+
+        assert!(within(&snapshot, LimitQuantity::Voltage, 3.3.into(), 0.05));
+        assert!(!within(&snapshot, LimitQuantity::Voltage, 3.3.into(), 0.01));
+        assert!(!within(&snapshot, LimitQuantity::Current, 1.0.into(), 0.05));
+    }
+}
@@ -0,0 +1,98 @@
+use std::time::SystemTime;
+
+use crate::commands::{
+    Channel, ChannelMode, ChannelStatus, DisplayMode, GetLimitRequest, GetLimitResponse,
+    LimitQuantity, MeasureRequest, MeasureResponse, OperationMode, Quantity, State,
+};
+
+/// State of one channel, read within a single round-trip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelSnapshot {
+    pub timestamp: SystemTime,
+    pub channel: Channel,
+    pub voltage_limit: f32,
+    pub current_limit: f32,
+    pub voltage: f32,
+    pub current: f32,
+    pub power: f32,
+    pub mode: ChannelMode,
+    pub output: State,
+    pub timer: State,
+    pub display: DisplayMode,
+}
+
+/// State of both channels, read within a single round-trip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub timestamp: SystemTime,
+    pub operation_mode: OperationMode,
+    pub channel_one: ChannelSnapshot,
+    pub channel_two: ChannelSnapshot,
+}
+
+impl Snapshot {
+    pub fn get(&self, channel: Channel) -> &ChannelSnapshot {
+        match channel {
+            Channel::One => &self.channel_one,
+            Channel::Two => &self.channel_two,
+        }
+    }
+}
+
+pub(crate) type ChannelRequests = (
+    GetLimitRequest,
+    GetLimitRequest,
+    MeasureRequest,
+    MeasureRequest,
+    MeasureRequest,
+);
+
+pub(crate) type ChannelResponses = (
+    GetLimitResponse,
+    GetLimitResponse,
+    MeasureResponse,
+    MeasureResponse,
+    MeasureResponse,
+);
+
+pub(crate) fn channel_requests(channel: Channel) -> ChannelRequests {
+    let limit = |quantity| GetLimitRequest {
+        quantity,
+        channel: Some(channel),
+    };
+    let measure = |quantity| MeasureRequest {
+        quantity,
+        channel: Some(channel),
+    };
+    (
+        limit(LimitQuantity::Voltage),
+        limit(LimitQuantity::Current),
+        measure(Quantity::Voltage),
+        measure(Quantity::Current),
+        measure(Quantity::Power),
+    )
+}
+
+impl ChannelSnapshot {
+    pub(crate) fn new(
+        timestamp: SystemTime,
+        channel: Channel,
+        responses: ChannelResponses,
+        status: &ChannelStatus,
+    ) -> Self {
+        let (voltage_limit, current_limit, voltage, current, power) = responses;
+        ChannelSnapshot {
+            timestamp,
+            channel,
+            voltage_limit: voltage_limit.0.into(),
+            current_limit: current_limit.0.into(),
+            voltage: voltage.0.into(),
+            current: current.0.into(),
+            power: power.0.into(),
+            mode: status.mode,
+            output: status.output,
+            timer: status.timer,
+            display: status.display,
+        }
+    }
+}
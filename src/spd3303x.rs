@@ -1,10 +1,12 @@
 use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
-    EmptyResponse, Error, Result, ScpiDeserialize, ScpiRequest,
+    EmptyResponse, Error, MnemonicStyle, Result, ScpiDeserialize, ScpiRequest,
+    batch::{self, Batch, ChainingMode},
     channel_control::ChannelControl,
     check_empty,
     commands::{
@@ -18,17 +20,45 @@ use crate::{
         SystemStatusRequest, SystemVersionRequest, SystemVersionResponse, TimeInterval,
         TimingGroup, WaveformDisplayRequest,
     },
+    emergency_stop::EmergencyStopHandle,
     fixed_channel_control::FixedChannelControl,
+    safety::{SafetyLimits, Setpoints},
+    snapshot::{ChannelSnapshot, Snapshot, channel_requests},
+    timer_program::{TimerProgram, TimerStep},
 };
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
     net::{TcpSocket, TcpStream, lookup_host},
     sync::Mutex,
+    time::timeout,
 };
 
+/// How long [`ChainingMode::Auto`] waits for the responses to the chained probe,
+/// before assuming the device does not support chaining. Also the quiet time when draining.
+const CHAINED_RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 pub struct Spd3303x {
     reader: BufReader<ReadHalf<TcpStream>>,
-    writer: WriteHalf<TcpStream>,
+    /// Shared with [`EmergencyStopHandle`]s, which write between queued requests.
+    writer: Arc<Mutex<WriteHalf<TcpStream>>>,
+    style: MnemonicStyle,
+    chaining: ChainingMode,
+    /// Per channel, indexed by [`channel_index`].
+    safety_limits: [SafetyLimits; 2],
+}
+
+fn connection_closed() -> Error {
+    Error::IoError(std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "Connection closed before a response was received",
+    ))
+}
+
+fn channel_index(channel: Channel) -> usize {
+    match channel {
+        Channel::One => 0,
+        Channel::Two => 1,
+    }
 }
 
 impl Spd3303x {
@@ -69,10 +99,53 @@ impl Spd3303x {
 
         Spd3303x {
             reader,
-            writer: write_half,
+            writer: Arc::new(Mutex::new(write_half)),
+            style: MnemonicStyle::default(),
+            chaining: ChainingMode::default(),
+            safety_limits: Default::default(),
         }
     }
 
+    /// Sets the spelling of mnemonics in all requests sent on this connection,
+    /// e.g. [`MnemonicStyle::Short`] to reduce bytes when polling at high rates.
+    pub fn set_mnemonic_style(&mut self, style: MnemonicStyle) {
+        self.style = style;
+    }
+
+    pub fn get_mnemonic_style(&self) -> MnemonicStyle {
+        self.style
+    }
+
+    pub fn set_chaining_mode(&mut self, mode: ChainingMode) {
+        self.chaining = mode;
+    }
+
+    pub fn get_chaining_mode(&self) -> ChainingMode {
+        self.chaining
+    }
+
+    /// Caps the setpoints of `channel`. [`Spd3303x::set_limit`], [`Spd3303x::set_timing_parameters`]
+    /// and [`Spd3303x::execute_batch`] fail with [`Error::SafetyLimit`] instead of sending a value
+    /// above the caps. [`Spd3303x::recall`] switches all outputs off first and restores the
+    /// previous setpoints and timer groups if the recalled ones exceed the caps.
+    pub fn set_safety_limits(&mut self, channel: Channel, limits: SafetyLimits) {
+        self.safety_limits[channel_index(channel)] = limits;
+    }
+
+    pub fn get_safety_limits(&self, channel: Channel) -> SafetyLimits {
+        self.safety_limits[channel_index(channel)]
+    }
+
+    fn check_safety_limits(
+        &self,
+        channel: Channel,
+        quantity: LimitQuantity,
+        value: Reading,
+    ) -> Result<()> {
+        self.get_safety_limits(channel)
+            .check(channel, quantity, value)
+    }
+
     pub async fn verify_serial_number(&mut self, serial_number: &str) -> Result<()> {
         let device_serial_number = self.get_identity().await?.serial_number;
 
@@ -84,6 +157,12 @@ impl Spd3303x {
             )))?
     }
 
+    /// Handle switching all outputs off over this connection without waiting for pending requests,
+    /// see [`EmergencyStop`](crate::emergency_stop::EmergencyStop).
+    pub fn emergency_stop_handle(&self) -> EmergencyStopHandle {
+        EmergencyStopHandle::new(self.writer.clone())
+    }
+
     pub fn into_channels(self) -> (ChannelControl, ChannelControl, FixedChannelControl) {
         let spd = Arc::new(Mutex::new(self));
         (
@@ -98,10 +177,13 @@ impl Spd3303x {
         Request: ScpiRequest,
     {
         let mut out = String::with_capacity(128);
-        request.serialize(&mut out);
-        out.push('\n');
-        self.writer.write_all(out.as_bytes()).await?;
+        request.serialize_styled(&mut out, self.style);
+        self.write_line(out).await
+    }
 
+    async fn write_line(&mut self, mut line: String) -> Result<()> {
+        line.push('\n');
+        self.writer.lock().await.write_all(line.as_bytes()).await?;
         Ok(())
     }
 
@@ -118,9 +200,7 @@ impl Spd3303x {
     {
         self.send_raw(request).await?;
 
-        let mut line = String::new();
-
-        self.reader.read_line(&mut line).await?;
+        let line = self.read_response_line().await?;
         let data = line.as_str();
 
         let mut data = data;
@@ -130,6 +210,141 @@ impl Spd3303x {
         Ok(response)
     }
 
+    /// Executes several requests in one round-trip, e.g. a tuple of measurement requests,
+    /// returning the responses in the same order.
+    ///
+    /// Requests are chained with `;` into a single line, depending on the [`ChainingMode`].
+    /// Setpoints are checked against the safety limits before anything is sent, recalls are
+    /// refused while limits are configured, see [`Spd3303x::set_safety_limits`].
+    /// With [`ChainingMode::Auto`], the first batch is preceded by a chained query-only probe,
+    /// which resolves the mode for this connection. Requests are never sent twice.
+    pub async fn execute_batch<B: Batch>(&mut self, batch: &B) -> Result<B::Responses> {
+        let requests = batch.serialize_each(self.style);
+        if requests.is_empty() {
+            return B::deserialize_each(&mut std::iter::empty());
+        }
+        self.check_batch(batch.setpoints_each())?;
+
+        if self.chaining == ChainingMode::Auto {
+            self.chaining = if self.probe_chaining().await? {
+                ChainingMode::Chained
+            } else {
+                ChainingMode::Sequential
+            };
+        }
+
+        let lines = match self.chaining {
+            ChainingMode::Sequential => self.exchange_sequential(requests).await?,
+            _ => self.exchange_chained(&requests).await?,
+        };
+        B::deserialize_each(&mut lines.into_iter())
+    }
+
+    /// Checks the setpoints of a batch as [`Spd3303x::set_limit`] and
+    /// [`Spd3303x::set_timing_parameters`] would, requests with unknown setpoints are refused.
+    fn check_batch(&self, setpoints: Vec<Setpoints>) -> Result<()> {
+        if self.safety_limits.iter().all(SafetyLimits::is_unlimited) {
+            return Ok(());
+        }
+        for setpoints in setpoints {
+            let values = match setpoints {
+                Setpoints::Unchanged => continue,
+                Setpoints::Values(values) => values,
+                Setpoints::Unknown => {
+                    return Err(Error::SafetyLimit(
+                        "Recall in a batch cannot be checked, use `Spd3303x::recall`".to_string(),
+                    ));
+                }
+            };
+            for (channel, quantity, value) in values {
+                // Without a channel, the selected one is set, which is not known here.
+                let channels = match channel {
+                    Some(channel) => vec![channel],
+                    None => vec![Channel::One, Channel::Two],
+                };
+                for channel in channels {
+                    self.check_safety_limits(channel, quantity, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends two chained identity queries, which have no side effects. If they are not both
+    /// answered in time, reads until the connection is quiet, so that late responses are not
+    /// taken as answers to later requests.
+    async fn probe_chaining(&mut self) -> Result<bool> {
+        let probe = (IdentityRequest, IdentityRequest);
+        let requests = probe.serialize_each(self.style);
+        let answered = timeout(CHAINED_RESPONSE_TIMEOUT, self.exchange_chained(&requests))
+            .await
+            .is_ok_and(|lines| {
+                lines.is_ok_and(|lines| {
+                    lines.len() == 2
+                        && <(IdentityRequest, IdentityRequest)>::deserialize_each(
+                            &mut lines.into_iter(),
+                        )
+                        .is_ok()
+                })
+            });
+        if answered {
+            return Ok(true);
+        }
+
+        let mut line = String::new();
+        while let Ok(read) =
+            timeout(CHAINED_RESPONSE_TIMEOUT, self.reader.read_line(&mut line)).await
+        {
+            if read? == 0 {
+                return Err(connection_closed());
+            }
+            line.clear();
+        }
+        Ok(false)
+    }
+
+    async fn exchange_chained(&mut self, requests: &[(String, bool)]) -> Result<Vec<String>> {
+        let line = requests
+            .iter()
+            .map(|(request, _)| request.as_str())
+            .collect::<Vec<_>>()
+            .join(&batch::SEPARATOR.to_string());
+        self.write_line(line).await?;
+
+        // Responses may arrive on one line, separated like the requests, or on separate lines.
+        let expected = requests.iter().filter(|(_, answered)| *answered).count();
+        let mut responses = Vec::with_capacity(expected);
+        while responses.len() < expected {
+            let line = self.read_response_line().await?;
+            responses.extend(
+                line.trim_end()
+                    .split(batch::SEPARATOR)
+                    .map(|response| format!("{response}\n")),
+            );
+        }
+        Ok(responses)
+    }
+
+    /// One response line, fails if the device closed the connection.
+    async fn read_response_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(connection_closed());
+        }
+        Ok(line)
+    }
+
+    async fn exchange_sequential(&mut self, requests: Vec<(String, bool)>) -> Result<Vec<String>> {
+        let mut responses = Vec::new();
+        for (request, answered) in requests {
+            self.write_line(request).await?;
+            if answered {
+                responses.push(self.read_response_line().await?);
+            }
+        }
+        Ok(responses)
+    }
+
     pub async fn get_identity(&mut self) -> Result<IdentityResponse> {
         self.execute(IdentityRequest).await
     }
@@ -138,8 +353,71 @@ impl Spd3303x {
         self.send(SaveRequest { slot }).await
     }
 
+    /// With safety limits configured, all outputs are switched off before recalling and stay
+    /// off, also if the recall succeeds, so recalled setpoints are never applied before they
+    /// were checked. If the recalled setpoints or timer groups exceed the limits, the previous
+    /// ones are restored.
     pub async fn recall(&mut self, slot: MemorySlot) -> Result<()> {
-        self.send(RecallRequest { slot }).await
+        if self.safety_limits.iter().all(SafetyLimits::is_unlimited) {
+            return self.send(RecallRequest { slot }).await;
+        }
+
+        // The contents of a slot can only be read after recalling it.
+        let previous = self.read_setpoints().await?;
+        let previous_groups = self.read_all_timer_groups().await?;
+        self.all_outputs_off().await?;
+        self.send(RecallRequest { slot }).await?;
+        let recalled = self.read_setpoints().await?;
+        let recalled_groups = self.read_all_timer_groups().await?;
+
+        let steps = recalled_groups.iter().flat_map(|&(channel, _, step)| {
+            [
+                (channel, LimitQuantity::Voltage, step.voltage),
+                (channel, LimitQuantity::Current, step.current),
+            ]
+        });
+        let violation =
+            recalled
+                .iter()
+                .copied()
+                .chain(steps)
+                .find_map(|(channel, quantity, value)| {
+                    self.check_safety_limits(channel, quantity, value).err()
+                });
+        let Some(violation) = violation else {
+            return Ok(());
+        };
+        for (channel, quantity, value) in previous {
+            self.send(SetLimitRequest {
+                quantity,
+                value,
+                channel: Some(channel),
+            })
+            .await?;
+        }
+        // Restored unchecked, these were on the device before the recall.
+        for (channel, group, step) in previous_groups {
+            self.send(SetTimingParametersRequest {
+                channel,
+                group,
+                voltage: step.voltage,
+                current: step.current,
+                time: step.time,
+            })
+            .await?;
+        }
+        Err(violation)
+    }
+
+    async fn read_setpoints(&mut self) -> Result<Vec<(Channel, LimitQuantity, Reading)>> {
+        let mut setpoints = Vec::with_capacity(4);
+        for channel in [Channel::One, Channel::Two] {
+            for quantity in [LimitQuantity::Voltage, LimitQuantity::Current] {
+                let value = self.get_limit(channel, quantity).await?;
+                setpoints.push((channel, quantity, Reading::from(value)));
+            }
+        }
+        Ok(setpoints)
     }
 
     pub async fn get_selected_channel(&mut self) -> Result<Channel> {
@@ -156,12 +434,30 @@ impl Spd3303x {
         Ok(response.0.into())
     }
 
+    /// Measures several quantities of `channel` in a single batch, in order.
+    pub async fn measure_each(
+        &mut self,
+        channel: Channel,
+        quantities: &[Quantity],
+    ) -> Result<Vec<f32>> {
+        let requests = quantities
+            .iter()
+            .map(|&quantity| MeasureRequest {
+                quantity,
+                channel: Some(channel),
+            })
+            .collect::<Vec<_>>();
+        let responses = self.execute_batch(&requests).await?;
+        Ok(responses.into_iter().map(|e| e.0.into()).collect())
+    }
+
     pub async fn set_limit(
         &mut self,
         channel: Channel,
         quantity: LimitQuantity,
         value: Reading,
     ) -> Result<()> {
+        self.check_safety_limits(channel, quantity, value)?;
         self.send(SetLimitRequest {
             quantity,
             value,
@@ -184,6 +480,16 @@ impl Spd3303x {
         self.send(SetOutputStateRequest { channel, state }).await
     }
 
+    /// Switches off all three outputs, attempting each even if one fails.
+    pub async fn all_outputs_off(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for channel in [OutputChannel::One, OutputChannel::Two, OutputChannel::Three] {
+            let switched = self.set_output(channel, State::Off).await;
+            result = result.and(switched);
+        }
+        result
+    }
+
     pub async fn set_output_mode(&mut self, mode: OperationMode) -> Result<()> {
         self.send(SetOperationModeRequest { mode }).await
     }
@@ -200,6 +506,8 @@ impl Spd3303x {
         current: Reading,
         time: TimeInterval,
     ) -> Result<()> {
+        self.check_safety_limits(channel, LimitQuantity::Voltage, voltage)?;
+        self.check_safety_limits(channel, LimitQuantity::Current, current)?;
         self.send(SetTimingParametersRequest {
             channel,
             group,
@@ -220,6 +528,48 @@ impl Spd3303x {
             .await
     }
 
+    /// Writes all five timer groups of `channel`, groups not used by `program` are cleared.
+    pub async fn upload_timer_program(
+        &mut self,
+        channel: Channel,
+        program: &TimerProgram,
+    ) -> Result<()> {
+        for (group, step) in program.groups() {
+            self.set_timing_parameters(channel, group, step.voltage, step.current, step.time)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reads all five timer groups of `channel` in a single batch.
+    pub async fn download_timer_program(&mut self, channel: Channel) -> Result<TimerProgram> {
+        Ok(TimerProgram::from_groups(
+            self.read_timer_groups(channel).await?,
+        ))
+    }
+
+    async fn read_timer_groups(&mut self, channel: Channel) -> Result<Vec<TimerStep>> {
+        let requests = TimerProgram::GROUPS
+            .map(|group| GetTimingParametersRequest { channel, group })
+            .to_vec();
+        let responses = self.execute_batch(&requests).await?;
+        Ok(responses.into_iter().map(TimerStep::from).collect())
+    }
+
+    async fn read_all_timer_groups(&mut self) -> Result<Vec<(Channel, TimingGroup, TimerStep)>> {
+        let mut groups = Vec::with_capacity(2 * TimerProgram::GROUPS.len());
+        for channel in [Channel::One, Channel::Two] {
+            let steps = self.read_timer_groups(channel).await?;
+            groups.extend(
+                TimerProgram::GROUPS
+                    .into_iter()
+                    .zip(steps)
+                    .map(|(group, step)| (channel, group, step)),
+            );
+        }
+        Ok(groups)
+    }
+
     pub async fn set_timer(&mut self, channel: Channel, state: State) -> Result<()> {
         self.send(SetTimerStateRequest { channel, state }).await
     }
@@ -272,4 +622,81 @@ impl Spd3303x {
         let status = self.get_status().await?;
         Ok(status.get(channel).output)
     }
+
+    /// Reads limits, measurements and status of one channel in a single batch.
+    pub async fn channel_snapshot(&mut self, channel: Channel) -> Result<ChannelSnapshot> {
+        let timestamp = SystemTime::now();
+        let (voltage_limit, current_limit, voltage, current, power) = channel_requests(channel);
+        let (voltage_limit, current_limit, voltage, current, power, status) = self
+            .execute_batch(&(
+                voltage_limit,
+                current_limit,
+                voltage,
+                current,
+                power,
+                SystemStatusRequest,
+            ))
+            .await?;
+
+        Ok(ChannelSnapshot::new(
+            timestamp,
+            channel,
+            (voltage_limit, current_limit, voltage, current, power),
+            status.decode().get(channel),
+        ))
+    }
+
+    /// Reads limits, measurements and status of both channels in a single batch.
+    pub async fn snapshot(&mut self) -> Result<Snapshot> {
+        let timestamp = SystemTime::now();
+        let (voltage_limit_1, current_limit_1, voltage_1, current_1, power_1) =
+            channel_requests(Channel::One);
+        let (voltage_limit_2, current_limit_2, voltage_2, current_2, power_2) =
+            channel_requests(Channel::Two);
+        let responses = self
+            .execute_batch(&(
+                voltage_limit_1,
+                current_limit_1,
+                voltage_1,
+                current_1,
+                power_1,
+                voltage_limit_2,
+                current_limit_2,
+                voltage_2,
+                current_2,
+                power_2,
+                SystemStatusRequest,
+            ))
+            .await?;
+        let status = responses.10.decode();
+
+        Ok(Snapshot {
+            timestamp,
+            operation_mode: status.operation_mode,
+            channel_one: ChannelSnapshot::new(
+                timestamp,
+                Channel::One,
+                (
+                    responses.0,
+                    responses.1,
+                    responses.2,
+                    responses.3,
+                    responses.4,
+                ),
+                &status.channel_one,
+            ),
+            channel_two: ChannelSnapshot::new(
+                timestamp,
+                Channel::Two,
+                (
+                    responses.5,
+                    responses.6,
+                    responses.7,
+                    responses.8,
+                    responses.9,
+                ),
+                &status.channel_two,
+            ),
+        })
+    }
 }
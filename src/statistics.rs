@@ -0,0 +1,333 @@
+//! Running statistics and energy/charge accumulation over sampled readings,
+//! e.g. from [`ChannelControl::measurements`].
+//!
+//! [`ChannelControl::measurements`]: crate::channel_control::ChannelControl::measurements
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{commands::Quantity, sampling::Sample};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub rms: f32,
+    /// Population standard deviation.
+    pub stddev: f32,
+}
+
+impl Statistics {
+    /// `None` if there are no values.
+    pub fn from_values(values: impl Iterator<Item = f32> + Clone) -> Option<Self> {
+        let values = || values.clone().map(f64::from);
+        let count = values().count();
+        if count == 0 {
+            return None;
+        }
+
+        let mean = values().sum::<f64>() / count as f64;
+        let mean_square = values().map(|value| value * value).sum::<f64>() / count as f64;
+        let variance = values().map(|value| (value - mean).powi(2)).sum::<f64>() / count as f64;
+
+        Some(Statistics {
+            count,
+            min: values().fold(f64::INFINITY, f64::min) as f32,
+            max: values().fold(f64::NEG_INFINITY, f64::max) as f32,
+            mean: mean as f32,
+            rms: mean_square.sqrt() as f32,
+            stddev: variance.sqrt() as f32,
+        })
+    }
+}
+
+/// Readings of the last `window` duration, older readings are evicted on [`SlidingWindow::push`].
+#[derive(Debug, Clone)]
+pub struct SlidingWindow {
+    window: Duration,
+    readings: VecDeque<(Instant, f32)>,
+}
+
+impl SlidingWindow {
+    pub fn new(window: Duration) -> Self {
+        SlidingWindow {
+            window,
+            readings: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, instant: Instant, value: f32) {
+        self.readings.push_back((instant, value));
+        while let Some((oldest, _)) = self.readings.front() {
+            if instant.saturating_duration_since(*oldest) <= self.window {
+                break;
+            }
+            self.readings.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.readings.clear();
+    }
+
+    /// Statistics of the readings in the window, `None` if it is empty.
+    pub fn statistics(&self) -> Option<Statistics> {
+        Statistics::from_values(self.readings.iter().map(|(_, value)| *value))
+    }
+}
+
+/// Trapezoidal integration of readings over time, in value-hours (e.g. Wh for power).
+///
+/// Intervals longer than `max_gap` are not integrated, as the readings in between are unknown;
+/// they are counted in [`Integrator::gaps`].
+#[derive(Debug, Clone)]
+pub struct Integrator {
+    max_gap: Duration,
+    previous: Option<(Instant, f32)>,
+    total: f64,
+    lap_start: f64,
+    gaps: u32,
+}
+
+impl Integrator {
+    pub fn new(max_gap: Duration) -> Self {
+        Integrator {
+            max_gap,
+            previous: None,
+            total: 0.0,
+            lap_start: 0.0,
+            gaps: 0,
+        }
+    }
+
+    pub fn push(&mut self, instant: Instant, value: f32) {
+        if let Some((previous_instant, previous_value)) = self.previous {
+            let elapsed = instant.saturating_duration_since(previous_instant);
+            if elapsed > self.max_gap {
+                self.gaps += 1;
+            } else {
+                let hours = elapsed.as_secs_f64() / 3600.0;
+                self.total += (f64::from(previous_value) + f64::from(value)) / 2.0 * hours;
+            }
+        }
+        self.previous = Some((instant, value));
+    }
+
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Integral since the previous lap (or reset), starts the next lap.
+    pub fn lap(&mut self) -> f64 {
+        let lap = self.total - self.lap_start;
+        self.lap_start = self.total;
+        lap
+    }
+
+    pub fn gaps(&self) -> u32 {
+        self.gaps
+    }
+
+    pub fn reset(&mut self) {
+        *self = Integrator::new(self.max_gap);
+    }
+}
+
+/// Energy and charge consumed during one lap, see [`ChannelStatistics::lap`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lap {
+    pub duration: Duration,
+    pub energy_wh: f64,
+    pub charge_ah: f64,
+}
+
+/// Sliding window statistics per quantity and integrated energy/charge of one channel.
+#[derive(Debug, Clone)]
+pub struct ChannelStatistics {
+    voltage: SlidingWindow,
+    current: SlidingWindow,
+    power: SlidingWindow,
+    energy: Integrator,
+    charge: Integrator,
+    lap_start: Option<Instant>,
+    latest: Option<Instant>,
+}
+
+impl ChannelStatistics {
+    /// Statistics cover the last `window`, samples further apart than `max_gap`
+    /// are not integrated.
+    pub fn new(window: Duration, max_gap: Duration) -> Self {
+        ChannelStatistics {
+            voltage: SlidingWindow::new(window),
+            current: SlidingWindow::new(window),
+            power: SlidingWindow::new(window),
+            energy: Integrator::new(max_gap),
+            charge: Integrator::new(max_gap),
+            lap_start: None,
+            latest: None,
+        }
+    }
+
+    /// Adds a sample, power is derived from voltage and current if not measured.
+    pub fn push(&mut self, sample: &Sample) {
+        let instant = sample.instant;
+        self.lap_start.get_or_insert(instant);
+        self.latest = Some(instant);
+
+        if let Some(voltage) = sample.voltage {
+            self.voltage.push(instant, voltage);
+        }
+        if let Some(current) = sample.current {
+            self.current.push(instant, current);
+            self.charge.push(instant, current);
+        }
+        let power = sample
+            .power
+            .or(sample.voltage.zip(sample.current).map(|(v, i)| v * i));
+        if let Some(power) = power {
+            self.power.push(instant, power);
+            self.energy.push(instant, power);
+        }
+    }
+
+    pub fn statistics(&self, quantity: Quantity) -> Option<Statistics> {
+        match quantity {
+            Quantity::Voltage => self.voltage.statistics(),
+            Quantity::Current => self.current.statistics(),
+            Quantity::Power => self.power.statistics(),
+        }
+    }
+
+    pub fn energy_wh(&self) -> f64 {
+        self.energy.total()
+    }
+
+    pub fn charge_ah(&self) -> f64 {
+        self.charge.total()
+    }
+
+    /// Number of gaps in integration, i.e. samples further apart than `max_gap`.
+    pub fn gaps(&self) -> u32 {
+        self.energy.gaps().max(self.charge.gaps())
+    }
+
+    /// Consumption since the previous lap (or reset), starts the next lap,
+    /// e.g. to measure individual test phases.
+    pub fn lap(&mut self) -> Lap {
+        let duration = self
+            .latest
+            .zip(self.lap_start)
+            .map(|(latest, start)| latest.saturating_duration_since(start))
+            .unwrap_or_default();
+        self.lap_start = self.latest;
+        Lap {
+            duration,
+            energy_wh: self.energy.lap(),
+            charge_ah: self.charge.lap(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.voltage.clear();
+        self.current.clear();
+        self.power.clear();
+        self.energy.reset();
+        self.charge.reset();
+        self.lap_start = None;
+        self.latest = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::commands::Channel;
+
+    #[test]
+    fn test_sliding_window() {
+        let start = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(2));
+        assert!(window.statistics().is_none());
+
+        for (second, value) in [(0, 10.0), (1, 1.0), (2, 3.0), (3, -1.0)] {
+            window.push(start + Duration::from_secs(second), value);
+        }
+
+        // The reading at 0s is evicted.
+        let statistics = window.statistics().unwrap();
+        assert_eq!(statistics.count, 3);
+        assert_eq!(statistics.min, -1.0);
+        assert_eq!(statistics.max, 3.0);
+        assert_eq!(statistics.mean, 1.0);
+        assert!((statistics.rms - (11.0f32 / 3.0).sqrt()).abs() < 1e-6);
+        assert!((statistics.stddev - (8.0f32 / 3.0).sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_integrator() {
+        let start = Instant::now();
+        let mut integrator = Integrator::new(Duration::from_secs(3600));
+
+        // 1 A to 3 A linearly over half an hour is 1 Ah.
+        integrator.push(start, 1.0);
+        integrator.push(start + Duration::from_secs(1800), 3.0);
+        assert!((integrator.total() - 1.0).abs() < 1e-9);
+        assert_eq!(integrator.gaps(), 0);
+
+        assert!((integrator.lap() - 1.0).abs() < 1e-9);
+        integrator.push(start + Duration::from_secs(1800 + 36), 3.0);
+        assert!((integrator.lap() - 0.03).abs() < 1e-9);
+        assert!((integrator.total() - 1.03).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_integrator_gap() {
+        let start = Instant::now();
+        let mut integrator = Integrator::new(Duration::from_secs(10));
+
+        integrator.push(start, 1.0);
+        integrator.push(start + Duration::from_secs(3600), 1.0);
+        integrator.push(start + Duration::from_secs(3636), 1.0);
+        assert_eq!(integrator.gaps(), 2);
+        assert_eq!(integrator.total(), 0.0);
+
+        integrator.push(start + Duration::from_secs(3645), 1.0);
+        assert!((integrator.total() - 0.0025).abs() < 1e-9);
+
+        integrator.reset();
+        assert_eq!(integrator.gaps(), 0);
+    }
+
+    #[test]
+    fn test_channel_statistics() {
+        let start = Instant::now();
+        let sample = |second, current| Sample {
+            channel: Channel::One,
+            instant: start + Duration::from_secs(second),
+            timestamp: SystemTime::now(),
+            latency: Duration::ZERO,
+            missed: 0,
+            voltage: Some(5.0),
+            current: Some(current),
+            power: None,
+        };
+
+        let mut statistics =
+            ChannelStatistics::new(Duration::from_secs(60), Duration::from_secs(5));
+        statistics.push(&sample(0, 0.5));
+        statistics.push(&sample(3, 0.5));
+
+        // 2.5 W for 3 s
+        let lap = statistics.lap();
+        assert_eq!(lap.duration, Duration::from_secs(3));
+        assert!((lap.energy_wh - 2.5 * 3.0 / 3600.0).abs() < 1e-9);
+        assert!((lap.charge_ah - 0.5 * 3.0 / 3600.0).abs() < 1e-9);
+        assert_eq!(statistics.statistics(Quantity::Power).unwrap().mean, 2.5);
+    }
+}
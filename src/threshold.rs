@@ -0,0 +1,304 @@
+//! Finding the supply voltage at which a DUT changes behavior, e.g. its minimum operating
+//! voltage or brown-out point, see [`ChannelControl::find_threshold`].
+//!
+//! [`ChannelControl::find_threshold`]: crate::channel_control::ChannelControl::find_threshold
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::Mutex, time::sleep};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, LimitQuantity, Reading, State},
+    settle::set_voltage_and_settle,
+    snapshot::ChannelSnapshot,
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Search {
+    /// Halves the interval until it is no wider than `resolution`.
+    Bisect { resolution: Reading },
+    /// Steps up from `low` until the predicate changes.
+    SweepUp { step: Reading },
+    /// Steps down from `high` until the predicate changes, e.g. for brown-out points with
+    /// hysteresis.
+    SweepDown { step: Reading },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdConfig {
+    pub low: Reading,
+    pub high: Reading,
+    pub search: Search,
+    pub current_limit: Reading,
+    /// Voltage settling tolerance in volt, see [`set_voltage_and_settle`].
+    pub tolerance: f32,
+    pub timeout: Duration,
+    /// Waited after settling before evaluating the predicate, e.g. for the DUT to boot.
+    pub dwell: Duration,
+    /// Switches the output off after the search, also if it failed.
+    pub switch_off: bool,
+}
+
+impl ThresholdConfig {
+    pub fn new(low: Reading, high: Reading, search: Search, current_limit: Reading) -> Self {
+        ThresholdConfig {
+            low,
+            high,
+            search,
+            current_limit,
+            tolerance: 0.02,
+            timeout: Duration::from_secs(1),
+            dwell: Duration::ZERO,
+            switch_off: true,
+        }
+    }
+
+    /// Fails with [`Error::InvalidConfig`] unless `low < high`.
+    pub fn validate(&self) -> Result<()> {
+        if self.low.get_millis() >= self.high.get_millis() {
+            return Err(Error::InvalidConfig(format!(
+                "Threshold search needs low < high, got {:.3} V and {:.3} V",
+                f32::from(self.low),
+                f32::from(self.high)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Voltage evaluated first, `high` when sweeping down, otherwise `low`.
+    pub fn get_first_voltage(&self) -> Reading {
+        match self.search {
+            Search::SweepDown { .. } => self.high,
+            Search::Bisect { .. } | Search::SweepUp { .. } => self.low,
+        }
+    }
+}
+
+/// The predicate changes between `below` and `above`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    /// Middle of the interval, in volt.
+    pub voltage: f32,
+    /// Half the width of the interval, in volt.
+    pub uncertainty: f32,
+    pub below: Reading,
+    pub above: Reading,
+    /// Predicate result at `above`.
+    pub above_result: bool,
+    pub evaluations: u32,
+}
+
+impl Threshold {
+    fn new(below: Reading, above: Reading, above_result: bool, evaluations: u32) -> Self {
+        let (below_volts, above_volts) = (f32::from(below), f32::from(above));
+        Threshold {
+            voltage: (below_volts + above_volts) / 2.0,
+            uncertainty: (above_volts - below_volts).abs() / 2.0,
+            below,
+            above,
+            above_result,
+            evaluations,
+        }
+    }
+}
+
+/// Searches the interval of `config` for the voltage at which `evaluate` changes. `evaluate`
+/// must be monotonic over the interval and differ between `low` and `high`. Fails with
+/// [`Error::InvalidConfig`] unless `low < high`.
+pub async fn search(
+    config: &ThresholdConfig,
+    mut evaluate: impl AsyncFnMut(Reading) -> Result<bool>,
+) -> Result<Threshold> {
+    config.validate()?;
+    let (low, high) = (config.low.get_millis(), config.high.get_millis());
+    let not_found = |result: bool| {
+        Err(Error::Other(format!(
+            "Predicate is {result} over the whole interval {:.3} V to {:.3} V",
+            f32::from(config.low),
+            f32::from(config.high)
+        )))
+    };
+
+    match config.search {
+        Search::Bisect { resolution } => {
+            let (mut low, mut high) = (low, high);
+            let low_result = evaluate(Reading::from_millis(low)).await?;
+            let high_result = evaluate(Reading::from_millis(high)).await?;
+            if low_result == high_result {
+                return not_found(low_result);
+            }
+            let mut evaluations = 2;
+            while high - low > resolution.get_millis().max(1) {
+                let middle = low + (high - low) / 2;
+                evaluations += 1;
+                if evaluate(Reading::from_millis(middle)).await? == high_result {
+                    high = middle;
+                } else {
+                    low = middle;
+                }
+            }
+            Ok(Threshold::new(
+                Reading::from_millis(low),
+                Reading::from_millis(high),
+                high_result,
+                evaluations,
+            ))
+        }
+        Search::SweepUp { step } | Search::SweepDown { step } => {
+            let step = i32::from(step.get_millis().max(1));
+            let (start, end, step) = match config.search {
+                Search::SweepUp { .. } => (i32::from(low), i32::from(high), step),
+                _ => (i32::from(high), i32::from(low), -step),
+            };
+            let first = evaluate(Reading::from_millis(start as u16)).await?;
+            let mut evaluations = 1;
+            let mut previous = start;
+            while previous != end {
+                let voltage = if step > 0 {
+                    (previous + step).min(end)
+                } else {
+                    (previous + step).max(end)
+                };
+                evaluations += 1;
+                let result = evaluate(Reading::from_millis(voltage as u16)).await?;
+                if result != first {
+                    let (below, above) = (previous.min(voltage), previous.max(voltage));
+                    let above_result = if step > 0 { result } else { first };
+                    return Ok(Threshold::new(
+                        Reading::from_millis(below as u16),
+                        Reading::from_millis(above as u16),
+                        above_result,
+                        evaluations,
+                    ));
+                }
+                previous = voltage;
+            }
+            not_found(first)
+        }
+    }
+}
+
+/// Sets the current limit and the first voltage of the search, switches the output on and
+/// searches for the voltage at which `predicate` changes, evaluating it on a settled snapshot
+/// at each voltage. The config is validated before the device is touched.
+pub async fn find_threshold(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: &ThresholdConfig,
+    mut predicate: impl AsyncFnMut(ChannelSnapshot) -> Result<bool>,
+) -> Result<Threshold> {
+    config.validate()?;
+    let found = async {
+        {
+            let mut spd = spd.lock().await;
+            spd.set_limit(channel, LimitQuantity::Current, config.current_limit)
+                .await?;
+            spd.set_limit(channel, LimitQuantity::Voltage, config.get_first_voltage())
+                .await?;
+            spd.set_output(channel.into(), State::On).await?;
+        }
+        search(config, async |voltage| {
+            let (snapshot, _) = set_voltage_and_settle(
+                spd.clone(),
+                channel,
+                voltage,
+                config.tolerance,
+                config.timeout,
+            )
+            .await?;
+            if config.dwell.is_zero() {
+                return predicate(snapshot).await;
+            }
+            sleep(config.dwell).await;
+            let snapshot = spd.lock().await.channel_snapshot(channel).await?;
+            predicate(snapshot).await
+        })
+        .await
+    }
+    .await;
+
+    if config.switch_off {
+        let switched = spd
+            .lock()
+            .await
+            .set_output(channel.into(), State::Off)
+            .await;
+        return found.and_then(|threshold| switched.map(|_| threshold));
+    }
+    found
+}
+
+/// Finds the voltage at which the current drawn crosses `current`, in ampere.
+pub async fn find_current_threshold(
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    config: &ThresholdConfig,
+    current: f32,
+) -> Result<Threshold> {
+    find_threshold(spd, channel, config, async |snapshot| {
+        Ok(snapshot.current >= current)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(search: Search) -> ThresholdConfig {
+        ThresholdConfig::new(1.0.into(), 5.0.into(), search, 0.1.into())
+    }
+
+    #[tokio::test]
+    async fn test_bisect() {
+        let config = config(Search::Bisect {
+            resolution: 0.01.into(),
+        });
+        let threshold = search(&config, async |voltage| Ok(f32::from(voltage) >= 2.7))
+            .await
+            .unwrap();
+        assert!(threshold.below.get_millis() < 2700 && threshold.above.get_millis() >= 2700);
+        assert!(threshold.uncertainty <= 0.005);
+        assert!((threshold.voltage - 2.7).abs() <= 0.01);
+        assert!(threshold.above_result);
+
+        let constant = search(&config, async |_| Ok(true)).await;
+        assert!(constant.is_err());
+
+        let inverted = ThresholdConfig {
+            low: 5.0.into(),
+            high: 1.0.into(),
+            ..config
+        };
+        assert!(matches!(inverted.validate(), Err(Error::InvalidConfig(_))));
+        let invalid = search(&inverted, async |_| Ok(true)).await;
+        assert!(matches!(invalid, Err(Error::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sweep() {
+        // Falling predicate, e.g. the DUT stops responding above some voltage.
+        let config_up = config(Search::SweepUp { step: 0.5.into() });
+        let threshold = search(&config_up, async |voltage| Ok(f32::from(voltage) < 3.2))
+            .await
+            .unwrap();
+        assert_eq!(threshold.below, Reading::from(3.0));
+        assert_eq!(threshold.above, Reading::from(3.5));
+        assert!(!threshold.above_result);
+        assert_eq!(threshold.evaluations, 6);
+
+        let config_down = config(Search::SweepDown { step: 0.5.into() });
+        let threshold = search(&config_down, async |voltage| Ok(f32::from(voltage) >= 3.2))
+            .await
+            .unwrap();
+        assert_eq!(threshold.below, Reading::from(3.0));
+        assert_eq!(threshold.above, Reading::from(3.5));
+        assert!(threshold.above_result);
+        assert_eq!(threshold.uncertainty, 0.25);
+
+        assert_eq!(config_up.get_first_voltage(), Reading::from(1.0));
+        assert_eq!(config_down.get_first_voltage(), Reading::from(5.0));
+    }
+}
@@ -0,0 +1,341 @@
+//! Programs for the five timer groups of a channel, see [`ChannelControl::upload_timer_program`].
+//!
+//! [`ChannelControl::upload_timer_program`]: crate::channel_control::ChannelControl::upload_timer_program
+
+use crate::{
+    Error, Result,
+    commands::{GetTimingParametersResponse, Reading, TimeInterval, TimingGroup},
+};
+
+/// Maximum setpoints of CH1 and CH2.
+pub const MAX_VOLTAGE: Reading = Reading::from_millis(32000);
+pub const MAX_CURRENT: Reading = Reading::from_millis(3200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerStep {
+    pub voltage: Reading,
+    pub current: Reading,
+    pub time: TimeInterval,
+}
+
+impl TimerStep {
+    /// Written to groups not used by a program. It is not validated: used steps of zero duration
+    /// are rejected only so that unused groups can be told apart when reading a program back.
+    /// How the timer treats a group of zero duration is not documented for the device.
+    pub const UNUSED: TimerStep = TimerStep {
+        voltage: Reading::from_millis(0),
+        current: Reading::from_millis(0),
+        time: TimeInterval::ZERO,
+    };
+
+    fn validate(&self, group: TimingGroup) -> Result<()> {
+        let invalid = |reason: String| {
+            Err(Error::InvalidProgram(format!(
+                "Step of group {group:?} {reason}"
+            )))
+        };
+        if self.voltage.get_millis() > MAX_VOLTAGE.get_millis() {
+            return invalid(format!("exceeds {} V", f32::from(MAX_VOLTAGE)));
+        }
+        if self.current.get_millis() > MAX_CURRENT.get_millis() {
+            return invalid(format!("exceeds {} A", f32::from(MAX_CURRENT)));
+        }
+        if self.time.get_seconds() == 0 {
+            return invalid("has zero duration".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl From<GetTimingParametersResponse> for TimerStep {
+    fn from(value: GetTimingParametersResponse) -> Self {
+        // The device reports the time as a decimal, but only accepts whole seconds.
+        let seconds = value.time.round().clamp(0.0, f32::from(TimeInterval::MAX)) as u16;
+        TimerStep {
+            voltage: value.voltage,
+            current: value.current,
+            time: seconds.into(),
+        }
+    }
+}
+
+/// A group whose step on the device differs from the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerDifference {
+    pub group: TimingGroup,
+    pub program: TimerStep,
+    pub device: TimerStep,
+}
+
+/// Up to five steps, run in order of the timer groups once the timer of the channel is started.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TimerProgram {
+    steps: Vec<TimerStep>,
+}
+
+impl TimerProgram {
+    pub const GROUPS: [TimingGroup; 5] = [
+        TimingGroup::One,
+        TimingGroup::Two,
+        TimingGroup::Three,
+        TimingGroup::Four,
+        TimingGroup::Five,
+    ];
+
+    /// Fails with [`Error::InvalidProgram`] for more than five steps, setpoints out of range
+    /// or steps of zero duration.
+    pub fn new(steps: Vec<TimerStep>) -> Result<Self> {
+        if steps.len() > Self::GROUPS.len() {
+            return Err(Error::InvalidProgram(format!(
+                "{} steps exceed the {} timer groups",
+                steps.len(),
+                Self::GROUPS.len()
+            )));
+        }
+        for (step, group) in steps.iter().zip(Self::GROUPS) {
+            step.validate(group)?;
+        }
+        Ok(TimerProgram { steps })
+    }
+
+    /// Program as read from the device, trailing unused groups are dropped. Not validated.
+    pub(crate) fn from_groups(groups: Vec<TimerStep>) -> Self {
+        let mut steps = groups;
+        while steps
+            .last()
+            .is_some_and(|step| step.time.get_seconds() == 0)
+        {
+            steps.pop();
+        }
+        TimerProgram { steps }
+    }
+
+    pub fn get_steps(&self) -> &[TimerStep] {
+        &self.steps
+    }
+
+    /// Steps of all five groups, unused groups are [`TimerStep::UNUSED`].
+    pub fn groups(&self) -> [(TimingGroup, TimerStep); 5] {
+        let mut groups = Self::GROUPS.map(|group| (group, TimerStep::UNUSED));
+        for ((_, group_step), step) in groups.iter_mut().zip(&self.steps) {
+            *group_step = *step;
+        }
+        groups
+    }
+
+    /// Groups in which `device` differs from this program.
+    pub fn diff(&self, device: &TimerProgram) -> Vec<TimerDifference> {
+        self.groups()
+            .into_iter()
+            .zip(device.groups())
+            .filter(|((_, program), (_, device))| program != device)
+            .map(|((group, program), (_, device))| TimerDifference {
+                group,
+                program,
+                device,
+            })
+            .collect()
+    }
+
+    /// One step per row, `voltage,current,time` with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("voltage,current,time\n");
+        for step in &self.steps {
+            out.push_str(&format!(
+                "{:.3},{:.3},{}\n",
+                f64::from(step.voltage),
+                f64::from(step.current),
+                step.time.get_seconds()
+            ));
+        }
+        out
+    }
+
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let mut lines = csv.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some("voltage,current,time") {
+            return Err(Error::InvalidProgram(
+                "CSV header `voltage,current,time` missing".to_string(),
+            ));
+        }
+
+        let steps = lines
+            .map(|line| {
+                let columns = line.split(',').map(str::trim).collect::<Vec<_>>();
+                let [voltage, current, time] = columns[..] else {
+                    return Err(Error::InvalidProgram(format!(
+                        "Expected three columns in `{line}`"
+                    )));
+                };
+                step_from_values(parse(voltage)?, parse(current)?, parse(time)?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(steps)
+    }
+
+    /// `{"steps":[{"voltage":3.3,"current":0.5,"time":2},...]}`
+    pub fn to_json(&self) -> String {
+        let steps = self
+            .steps
+            .iter()
+            .map(|step| {
+                serde_json::json!({
+                    "voltage": f64::from(step.voltage),
+                    "current": f64::from(step.current),
+                    "time": step.time.get_seconds(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({ "steps": steps }).to_string()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(json)
+            .map_err(|e| Error::InvalidProgram(format!("Invalid JSON: {e}")))?;
+        let steps = value["steps"]
+            .as_array()
+            .ok_or(Error::InvalidProgram(
+                "JSON `steps` array missing".to_string(),
+            ))?
+            .iter()
+            .map(|step| {
+                let number = |key: &str| {
+                    step[key].as_f64().ok_or(Error::InvalidProgram(format!(
+                        "Number `{key}` missing in step `{step}`"
+                    )))
+                };
+                step_from_values(number("voltage")?, number("current")?, number("time")?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(steps)
+    }
+}
+
+fn parse(value: &str) -> Result<f64> {
+    value
+        .parse()
+        .map_err(|e| Error::InvalidProgram(format!("Invalid number `{value}`: {e}")))
+}
+
+/// Checks that the values are representable, device ranges are validated by [`TimerProgram::new`].
+fn step_from_values(voltage: f64, current: f64, time: f64) -> Result<TimerStep> {
+    let in_range = |value: f64, max: f64| (0.0..=max).contains(&value);
+    let max_reading = f64::from(Reading::from_millis(u16::MAX));
+    if !in_range(voltage, max_reading) || !in_range(current, max_reading) {
+        return Err(Error::InvalidProgram(format!(
+            "Setpoints {voltage} V, {current} A out of range"
+        )));
+    }
+    if time.fract() != 0.0 || !in_range(time, f64::from(TimeInterval::MAX)) {
+        return Err(Error::InvalidProgram(format!(
+            "Time {time} is not a whole number of seconds up to {}",
+            TimeInterval::MAX
+        )));
+    }
+    Ok(TimerStep {
+        voltage: voltage.into(),
+        current: current.into(),
+        time: (time as u16).into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScpiDeserialize;
+
+    fn step(voltage: f32, current: f32, time: u16) -> TimerStep {
+        TimerStep {
+            voltage: voltage.into(),
+            current: current.into(),
+            time: time.into(),
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(TimerProgram::new(vec![step(3.3, 0.5, 2); 5]).is_ok());
+        assert!(TimerProgram::new(vec![step(3.3, 0.5, 2); 6]).is_err());
+        assert!(TimerProgram::new(vec![step(33.0, 0.5, 2)]).is_err());
+        assert!(TimerProgram::new(vec![step(3.3, 3.3, 2)]).is_err());
+        assert!(TimerProgram::new(vec![step(3.3, 0.5, 0)]).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let program = TimerProgram::new(vec![step(3.3, 0.5, 2), step(5.0, 1.0, 10)]).unwrap();
+        let device = TimerProgram::from_groups(vec![
+            step(3.3, 0.5, 2),
+            step(5.0, 1.0, 5),
+            step(1.0, 0.1, 1),
+            TimerStep::UNUSED,
+            TimerStep::UNUSED,
+        ]);
+        assert_eq!(device.get_steps().len(), 3);
+
+        let differences = program.diff(&device);
+        assert_eq!(
+            differences,
+            vec![
+                TimerDifference {
+                    group: TimingGroup::Two,
+                    program: step(5.0, 1.0, 10),
+                    device: step(5.0, 1.0, 5),
+                },
+                TimerDifference {
+                    group: TimingGroup::Three,
+                    program: TimerStep::UNUSED,
+                    device: step(1.0, 0.1, 1),
+                },
+            ]
+        );
+        assert!(program.diff(&program).is_empty());
+    }
+
+    #[test]
+    fn test_csv() {
+        let program = TimerProgram::new(vec![step(3.3, 0.5, 2), step(5.0, 1.0, 10)]).unwrap();
+        let csv = program.to_csv();
+        assert_eq!(csv, "voltage,current,time\n3.300,0.500,2\n5.000,1.000,10\n");
+        assert_eq!(TimerProgram::from_csv(&csv).unwrap(), program);
+
+        assert!(TimerProgram::from_csv("3.3,0.5,2\n").is_err());
+        assert!(TimerProgram::from_csv("voltage,current,time\n3.3,0.5,2.5\n").is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        let program = TimerProgram::new(vec![step(3.3, 0.5, 2)]).unwrap();
+        let json = program.to_json();
+        assert_eq!(
+            json,
+            r#"{"steps":[{"current":0.5,"time":2,"voltage":3.3}]}"#
+        );
+        assert_eq!(TimerProgram::from_json(&json).unwrap(), program);
+
+        assert!(TimerProgram::from_json(r#"{"steps":[{"voltage":3.3}]}"#).is_err());
+    }
+
+    #[test]
+    fn test_step_from_response() {
+        let response = GetTimingParametersResponse {
+            voltage: Reading::from(3.0),
+            current: Reading::from(0.5),
+            time: 2.0,
+        };
+        assert_eq!(TimerStep::from(response), step(3.0, 0.5, 2));
+
+        let response =
+            GetTimingParametersResponse::deserialize(&mut "1.000,0.500,100.000\n").unwrap();
+        assert_eq!(TimerStep::from(response), step(1.0, 0.5, 100));
+
+        let response = GetTimingParametersResponse {
+            time: 20000.0,
+            ..response
+        };
+        assert_eq!(
+            TimerStep::from(response).time.get_seconds(),
+            TimeInterval::MAX
+        );
+    }
+}
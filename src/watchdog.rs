@@ -0,0 +1,206 @@
+//! Switches all outputs off when the controlling application stops sending heartbeats.
+//!
+//! The watchdog is meant to run in a separate process, so it survives a crash of the application.
+//! Heartbeats are UDP datagrams, see [`HeartbeatSender`] and [`udp_heartbeats`].
+
+use std::{future::Future, net::SocketAddr, pin::pin, time::Duration};
+
+use futures_util::{Stream, StreamExt, stream};
+use tokio::{
+    net::UdpSocket,
+    task::JoinHandle,
+    time::{Instant, interval, sleep, sleep_until, timeout},
+};
+
+use crate::{Result, sampling::check_period, spd3303x::Spd3303x};
+
+/// Payload of heartbeat datagrams, other datagrams are ignored.
+pub const HEARTBEAT: &[u8] = b"SPD3303X HEARTBEAT";
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    /// Outputs are switched off if no heartbeat arrives within this duration.
+    pub deadline: Duration,
+    /// Connection attempts not completed within this duration are retried.
+    pub timeout: Duration,
+    pub retry_delay: Duration,
+    /// Verified before switching off, so a wrong device is never touched.
+    pub serial_number: Option<String>,
+}
+
+impl WatchdogConfig {
+    pub fn new(deadline: Duration) -> Self {
+        WatchdogConfig {
+            deadline,
+            timeout: Duration::from_secs(5),
+            retry_delay: Duration::from_secs(1),
+            serial_number: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogExit {
+    Stopped,
+    /// Heartbeats were missed and all outputs were switched off.
+    Tripped,
+}
+
+/// Connects to the device only once heartbeats are missed, so the controlling application
+/// keeps its own connection in the meantime.
+pub struct Watchdog {
+    host: String,
+    config: WatchdogConfig,
+}
+
+impl Watchdog {
+    pub fn new(host: impl Into<String>, config: WatchdogConfig) -> Self {
+        Watchdog {
+            host: host.into(),
+            config,
+        }
+    }
+
+    /// Arms with the first heartbeat, then switches all outputs off once the deadline passes
+    /// without a heartbeat. Switching off is retried until it succeeds or `stop` completes.
+    pub async fn run(
+        &self,
+        heartbeats: impl Stream<Item = ()>,
+        stop: impl Future<Output = ()>,
+    ) -> Result<WatchdogExit> {
+        let mut heartbeats = pin!(heartbeats);
+        let mut stop = pin!(stop);
+
+        let mut deadline: Option<Instant> = None;
+        let mut ended = false;
+        loop {
+            let expired = async move {
+                match deadline {
+                    Some(deadline) => sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = &mut stop => return Ok(WatchdogExit::Stopped),
+                heartbeat = heartbeats.next(), if !ended => match heartbeat {
+                    Some(()) => deadline = Some(Instant::now() + self.config.deadline),
+                    // Without a source of heartbeats, an armed deadline passes eventually.
+                    None => ended = true,
+                },
+                _ = expired => break,
+            }
+        }
+
+        loop {
+            let switched = tokio::select! {
+                _ = &mut stop => return Ok(WatchdogExit::Stopped),
+                switched = timeout(self.config.timeout, self.outputs_off()) => switched,
+            };
+            if let Ok(Ok(())) = switched {
+                return Ok(WatchdogExit::Tripped);
+            }
+
+            tokio::select! {
+                _ = &mut stop => return Ok(WatchdogExit::Stopped),
+                _ = sleep(self.config.retry_delay) => {}
+            }
+        }
+    }
+
+    async fn outputs_off(&self) -> Result<()> {
+        let mut spd = Spd3303x::connect_hostname(&self.host).await?;
+        if let Some(serial_number) = &self.config.serial_number {
+            spd.verify_serial_number(serial_number).await?;
+        }
+        spd.all_outputs_off().await
+    }
+}
+
+/// Heartbeats received on `socket`.
+pub fn udp_heartbeats(socket: UdpSocket) -> impl Stream<Item = ()> {
+    stream::unfold(socket, |socket| async move {
+        // Larger than a heartbeat, so longer datagrams are not truncated into one.
+        let mut buffer = [0; 64];
+        loop {
+            match socket.recv(&mut buffer).await {
+                Ok(length) if buffer[..length] == *HEARTBEAT => return Some(((), socket)),
+                Ok(_) => continue,
+                Err(_) => return None,
+            }
+        }
+    })
+}
+
+/// Sends heartbeats to a watchdog from a background task, until dropped.
+pub struct HeartbeatSender {
+    task: JoinHandle<()>,
+}
+
+impl HeartbeatSender {
+    /// `period` should be well below the watchdog deadline, as datagrams may be lost.
+    /// Fails with [`Error::InvalidConfig`](crate::Error::InvalidConfig) for a zero `period`.
+    pub async fn spawn(watchdog: SocketAddr, period: Duration) -> Result<Self> {
+        check_period("Heartbeat period", period)?;
+        let bind: SocketAddr = match watchdog {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(watchdog).await?;
+
+        let task = tokio::spawn(async move {
+            let mut ticker = interval(period);
+            loop {
+                ticker.tick().await;
+                // A missing watchdog is not an error of the application.
+                let _ = socket.send(HEARTBEAT).await;
+            }
+        });
+        Ok(HeartbeatSender { task })
+    }
+}
+
+impl Drop for HeartbeatSender {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_heartbeat_zero_period() {
+        let watchdog = SocketAddr::from(([127, 0, 0, 1], 5026));
+        let sender = HeartbeatSender::spawn(watchdog, Duration::ZERO).await;
+        assert!(matches!(sender, Err(crate::Error::InvalidConfig(_))));
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_stop() {
+        let mut config = WatchdogConfig::new(Duration::from_secs(1));
+        config.retry_delay = Duration::from_secs(60);
+        let watchdog = Watchdog::new("localhost:1", config);
+
+        // Not armed without heartbeats.
+        let exit = watchdog
+            .run(stream::pending(), sleep(Duration::from_millis(10)))
+            .await
+            .unwrap();
+        assert_eq!(exit, WatchdogExit::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_udp_heartbeats() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let mut heartbeats = pin!(udp_heartbeats(socket));
+
+        let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        sender.send_to(b"other", address).await.unwrap();
+        sender.send_to(HEARTBEAT, address).await.unwrap();
+
+        assert_eq!(heartbeats.next().await, Some(()));
+    }
+}
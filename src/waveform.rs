@@ -0,0 +1,393 @@
+//! Host-timed playback of voltage or current profiles of arbitrary length,
+//! see [`ChannelControl::play`].
+//!
+//! [`ChannelControl::play`]: crate::channel_control::ChannelControl::play
+
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::{Mutex, watch},
+    task::JoinHandle,
+    time::{Instant, sleep_until},
+};
+
+use crate::{
+    Error, Result,
+    commands::{Channel, LimitQuantity, Reading, State},
+    spd3303x::Spd3303x,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Each value is held until the next point.
+    Step,
+    /// Values between points are interpolated every update period.
+    Linear,
+}
+
+/// Setpoints of one quantity over time, the time of the last point is the length of one loop.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    pub quantity: LimitQuantity,
+    pub interpolation: Interpolation,
+    /// Offsets from the start of the loop, in ascending order.
+    pub points: Vec<(Duration, Reading)>,
+    /// Time between interpolated setpoints.
+    pub update_period: Duration,
+}
+
+impl Waveform {
+    pub fn new(
+        quantity: LimitQuantity,
+        interpolation: Interpolation,
+        points: Vec<(Duration, Reading)>,
+    ) -> Result<Self> {
+        if points.is_empty() {
+            return Err(Error::InvalidWaveform("No points".to_string()));
+        }
+        if points.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+            return Err(Error::InvalidWaveform(
+                "Points are not in ascending order of time".to_string(),
+            ));
+        }
+        Ok(Waveform {
+            quantity,
+            interpolation,
+            points,
+            update_period: Duration::from_millis(100),
+        })
+    }
+
+    /// Fails with [`Error::InvalidWaveform`] if the waveform is repeated with a loop of zero
+    /// duration, which would send setpoints as fast as the device answers.
+    pub fn check_repeat(&self, repeat: Repeat) -> Result<()> {
+        if repeat != Repeat::Once && self.get_duration().is_zero() {
+            return Err(Error::InvalidWaveform(format!(
+                "Loop of zero duration cannot be repeated {repeat:?}"
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.points
+            .last()
+            .map(|(time, _)| *time)
+            .unwrap_or_default()
+    }
+
+    /// Setpoints to send within one loop, with their offsets from the start of the loop.
+    pub fn schedule(&self) -> Vec<(Duration, Reading)> {
+        match self.interpolation {
+            Interpolation::Step => self.points.clone(),
+            Interpolation::Linear => {
+                let mut schedule = Vec::new();
+                for pair in self.points.windows(2) {
+                    let ((start, from), (end, to)) = (pair[0], pair[1]);
+                    let mut time = start;
+                    while time < end {
+                        let fraction = (time - start).as_secs_f64() / (end - start).as_secs_f64();
+                        let from_millis = f64::from(from.get_millis());
+                        let to_millis = f64::from(to.get_millis());
+                        let millis = from_millis + (to_millis - from_millis) * fraction;
+                        schedule.push((time, Reading::from_millis(millis.round() as u16)));
+                        time += self.update_period.max(Duration::from_millis(1));
+                    }
+                }
+                schedule.extend(self.points.last().copied());
+                schedule
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    Once,
+    Times(u32),
+    /// Until aborted.
+    Forever,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlayState {
+    Playing,
+    Paused,
+    Aborted,
+}
+
+/// Setpoints recorded in a [`PlaybackReport`], later ones are only counted.
+pub const MAX_RECORDED_SETPOINTS: usize = 100_000;
+
+/// Timing of the setpoints actually sent.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlaybackReport {
+    pub updates: u32,
+    /// Setpoints in the order sent, with the times their requests were sent, as offsets from
+    /// the start of playback. Only the first [`MAX_RECORDED_SETPOINTS`] are recorded.
+    pub setpoints: Vec<(Duration, Reading)>,
+    pub loops: u32,
+    /// Aborted, or the handle was dropped, and the output switched off.
+    pub aborted: bool,
+    /// Deviation of the completed setpoint requests from their scheduled time.
+    pub mean_jitter: Duration,
+    pub max_jitter: Duration,
+    /// Estimated duration of a setpoint request, requests are sent this much earlier.
+    pub latency: Duration,
+}
+
+#[derive(Default)]
+struct JitterStatistics {
+    count: u32,
+    total: Duration,
+    max: Duration,
+}
+
+impl JitterStatistics {
+    fn record(&mut self, completed: Instant, scheduled: Instant) {
+        let jitter = completed
+            .saturating_duration_since(scheduled)
+            .max(scheduled.saturating_duration_since(completed));
+        self.count += 1;
+        self.total += jitter;
+        self.max = self.max.max(jitter);
+    }
+
+    fn mean(&self) -> Duration {
+        self.total.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+/// A waveform playing in a background task. Dropping the handle aborts playback.
+pub struct Playback {
+    state: watch::Sender<PlayState>,
+    task: JoinHandle<Result<PlaybackReport>>,
+}
+
+impl Playback {
+    /// Fails if `waveform` cannot be repeated as requested, see [`Waveform::check_repeat`].
+    pub fn spawn(
+        spd: Arc<Mutex<Spd3303x>>,
+        channel: Channel,
+        waveform: Waveform,
+        repeat: Repeat,
+    ) -> Result<Self> {
+        waveform.check_repeat(repeat)?;
+        let (state, receiver) = watch::channel(PlayState::Playing);
+        let player = Player {
+            spd,
+            channel,
+            state: receiver,
+            jitter: JitterStatistics::default(),
+            latency: Duration::ZERO,
+            started: Instant::now(),
+            setpoints: Vec::new(),
+        };
+        let task = tokio::spawn(player.play(waveform, repeat));
+        Ok(Playback { state, task })
+    }
+
+    /// Holds the current setpoint, the remaining schedule is shifted by the pause.
+    pub fn pause(&self) {
+        self.state.send_replace(PlayState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.state.send_replace(PlayState::Playing);
+    }
+
+    /// Stops playback and switches the output off.
+    pub fn abort(&self) {
+        self.state.send_replace(PlayState::Aborted);
+    }
+
+    /// Waits until playback completed or was aborted. On a failed request, the output is
+    /// switched off and the error is returned.
+    pub async fn finish(mut self) -> Result<PlaybackReport> {
+        (&mut self.task)
+            .await
+            .map_err(|e| Error::Other(format!("Playback task failed: {e}")))?
+    }
+}
+
+struct Player {
+    spd: Arc<Mutex<Spd3303x>>,
+    channel: Channel,
+    state: watch::Receiver<PlayState>,
+    jitter: JitterStatistics,
+    latency: Duration,
+    started: Instant,
+    setpoints: Vec<(Duration, Reading)>,
+}
+
+impl Player {
+    async fn play(mut self, waveform: Waveform, repeat: Repeat) -> Result<PlaybackReport> {
+        let schedule = waveform.schedule();
+        let mut loops = 0;
+        let played = loop {
+            let done = match repeat {
+                Repeat::Once => loops >= 1,
+                Repeat::Times(times) => loops >= times,
+                Repeat::Forever => false,
+            };
+            if done {
+                break Ok(true);
+            }
+            match self.play_loop(waveform.quantity, &schedule).await {
+                Ok(true) => loops += 1,
+                other => break other,
+            }
+        };
+
+        let aborted = match played {
+            Ok(completed) => !completed,
+            Err(error) => {
+                let _ = self.safe_off().await;
+                return Err(error);
+            }
+        };
+        if aborted {
+            self.safe_off().await?;
+        }
+
+        Ok(PlaybackReport {
+            updates: self.jitter.count,
+            setpoints: self.setpoints,
+            loops,
+            aborted,
+            mean_jitter: self.jitter.mean(),
+            max_jitter: self.jitter.max,
+            latency: self.latency,
+        })
+    }
+
+    /// Returns whether the loop completed, `false` if aborted.
+    async fn play_loop(
+        &mut self,
+        quantity: LimitQuantity,
+        schedule: &[(Duration, Reading)],
+    ) -> Result<bool> {
+        let mut start = Instant::now();
+        for (offset, value) in schedule {
+            loop {
+                let state = *self.state.borrow_and_update();
+                match state {
+                    PlayState::Aborted => return Ok(false),
+                    PlayState::Paused => {
+                        let paused = Instant::now();
+                        if self.state.changed().await.is_err() {
+                            return Ok(false);
+                        }
+                        start += paused.elapsed();
+                        continue;
+                    }
+                    PlayState::Playing => {}
+                }
+
+                let scheduled = start + *offset;
+                tokio::select! {
+                    _ = sleep_until(scheduled.checked_sub(self.latency).unwrap_or(scheduled)) => break,
+                    changed = self.state.changed() => if changed.is_err() {
+                        // The handle was dropped.
+                        return Ok(false);
+                    },
+                }
+            }
+
+            let sent = Instant::now();
+            self.spd
+                .lock()
+                .await
+                .set_limit(self.channel, quantity, *value)
+                .await?;
+            let completed = Instant::now();
+            if self.setpoints.len() < MAX_RECORDED_SETPOINTS {
+                self.setpoints.push((sent - self.started, *value));
+            }
+
+            // Exponential moving average, robust against single slow requests.
+            self.latency = (self.latency * 7 + (completed - sent)) / 8;
+            self.jitter.record(completed, start + *offset);
+        }
+        Ok(true)
+    }
+
+    async fn safe_off(&self) -> Result<()> {
+        self.spd
+            .lock()
+            .await
+            .set_output(self.channel.into(), State::Off)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Waveform::new(LimitQuantity::Voltage, Interpolation::Step, vec![]).is_err());
+        let unordered = vec![
+            (millis(100), Reading::from(1.0)),
+            (millis(0), Reading::from(2.0)),
+        ];
+        assert!(Waveform::new(LimitQuantity::Voltage, Interpolation::Step, unordered).is_err());
+
+        let instant = vec![(millis(0), Reading::from(1.0))];
+        let waveform = Waveform::new(LimitQuantity::Voltage, Interpolation::Step, instant).unwrap();
+        assert!(waveform.check_repeat(Repeat::Once).is_ok());
+        assert!(matches!(
+            waveform.check_repeat(Repeat::Forever),
+            Err(Error::InvalidWaveform(_))
+        ));
+        assert!(waveform.check_repeat(Repeat::Times(2)).is_err());
+    }
+
+    #[test]
+    fn test_step_schedule() {
+        let points = vec![
+            (millis(0), Reading::from(5.0)),
+            (millis(500), Reading::from(3.0)),
+        ];
+        let waveform =
+            Waveform::new(LimitQuantity::Voltage, Interpolation::Step, points.clone()).unwrap();
+        assert_eq!(waveform.schedule(), points);
+        assert_eq!(waveform.get_duration(), millis(500));
+    }
+
+    #[test]
+    fn test_linear_schedule() {
+        let points = vec![
+            (millis(0), Reading::from(1.0)),
+            (millis(300), Reading::from(4.0)),
+            (millis(400), Reading::from(4.0)),
+        ];
+        let waveform =
+            Waveform::new(LimitQuantity::Voltage, Interpolation::Linear, points).unwrap();
+        assert_eq!(
+            waveform.schedule(),
+            vec![
+                (millis(0), Reading::from(1.0)),
+                (millis(100), Reading::from(2.0)),
+                (millis(200), Reading::from(3.0)),
+                (millis(300), Reading::from(4.0)),
+                (millis(400), Reading::from(4.0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jitter_statistics() {
+        let now = Instant::now();
+        let mut jitter = JitterStatistics::default();
+        jitter.record(now + millis(10), now);
+        jitter.record(now, now + millis(30));
+        assert_eq!(jitter.mean(), millis(20));
+        assert_eq!(jitter.max, millis(30));
+    }
+}
@@ -1,8 +1,26 @@
+use std::{sync::Arc, time::Duration};
+
+use futures_util::StreamExt;
 use spd3303x::{
     Error, Result,
     channel_control::ChannelControl,
-    commands::{Channel, LimitQuantity, MemorySlot, OperationMode, Quantity, State},
+    commands::{
+        Channel, GetLimitRequest, LimitQuantity, MeasureRequest, MemorySlot, OperationMode,
+        OutputChannel, Quantity, SetLimitRequest, SetOutputStateRequest, State,
+        SystemStatusRequest,
+    },
+    emulation::{EmulationConfig, LiIonBattery},
+    endurance::{EnduranceConfig, run_endurance},
+    events::{StatusEvent, StatusEvents},
+    iv_curve::SweepConfig,
+    profiles::{CompiledProfile, Profile},
+    ramp::RampConfig,
+    safety::SafetyLimits,
+    sequencing::{Readiness, Sequence, Step},
     spd3303x::Spd3303x,
+    threshold::{Search, ThresholdConfig},
+    timer_program::{TimerProgram, TimerStep},
+    waveform::{Interpolation, Repeat, Waveform},
 };
 
 async fn test_device() -> Result<Spd3303x> {
@@ -109,6 +127,300 @@ async fn test_output() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_safety_limits() -> Result<()> {
+    let channel = test_channel().await?;
+    channel
+        .set_safety_limits(SafetyLimits::new(3.6.into(), 0.5.into()))
+        .await;
+
+    channel
+        .set_limit(LimitQuantity::Voltage, 3.3.into())
+        .await?;
+    let result = channel.set_limit(LimitQuantity::Voltage, 33.0.into()).await;
+    assert!(matches!(result, Err(Error::SafetyLimit(_))));
+    assert_eq!(channel.get_limit(LimitQuantity::Voltage).await?, 3.3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ramp() -> Result<()> {
+    let channel = test_channel().await?;
+    let config = RampConfig {
+        start_at_zero: true,
+        ..RampConfig::new(Duration::from_millis(500), 5)
+    };
+
+    channel.ramp(3.0.into(), &config).await?;
+    assert_eq!(channel.get_limit(LimitQuantity::Voltage).await?, 3.0);
+    assert_eq!(channel.get_output().await?, State::On);
+
+    channel
+        .ramp_down_and_off(Duration::from_millis(500), 5)
+        .await?;
+    assert_eq!(channel.get_limit(LimitQuantity::Voltage).await?, 0.0);
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sequence() -> Result<()> {
+    let (core, io, _) = test_device().await?.into_channels();
+    let mut io_step = Step::channel(&io, 3.3.into());
+    io_step.delay = Duration::from_millis(100);
+    io_step.ready = Some(Readiness::new(0.1, Duration::from_secs(1)));
+    let sequence = Sequence::new(vec![Step::channel(&core, 1.2.into()), io_step]);
+
+    sequence.power_up().await?;
+    assert_eq!(core.get_output().await?, State::On);
+    assert_eq!(io.get_output().await?, State::On);
+
+    sequence.power_down().await?;
+    assert_eq!(core.get_output().await?, State::Off);
+    assert_eq!(io.get_output().await?, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_and_settle() -> Result<()> {
+    let channel = test_channel().await?;
+    channel.set_output(State::On).await?;
+
+    let settled = channel
+        .set_and_settle(
+            LimitQuantity::Voltage,
+            2.5.into(),
+            0.05,
+            Duration::from_secs(2),
+        )
+        .await?;
+    assert!((settled.snapshot.voltage - 2.5).abs() <= 0.05);
+
+    let result = channel
+        .wait_until(
+            |snapshot| snapshot.voltage > 30.0,
+            Duration::from_millis(200),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::Timeout(_))));
+
+    channel.set_output(State::Off).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_timer_program() -> Result<()> {
+    let channel = test_channel().await?;
+    let step = |voltage: f32, current: f32, time: u16| TimerStep {
+        voltage: voltage.into(),
+        current: current.into(),
+        time: time.into(),
+    };
+    let program = TimerProgram::new(vec![step(3.3, 0.5, 2), step(5.0, 1.0, 3)])?;
+
+    channel.upload_timer_program(&program).await?;
+    assert_eq!(channel.download_timer_program().await?, program);
+    assert!(channel.diff_timer_program(&program).await?.is_empty());
+
+    channel.start_timer_program(&program).await?;
+    channel.set_timer(State::Off).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_waveform() -> Result<()> {
+    let channel = test_channel().await?;
+    let points = [(0, 1.0), (200, 3.0), (400, 1.0)]
+        .map(|(millis, volts)| (Duration::from_millis(millis), volts.into()))
+        .to_vec();
+    let waveform = Waveform::new(LimitQuantity::Voltage, Interpolation::Linear, points)?;
+
+    let report = channel
+        .play(waveform.clone(), Repeat::Times(2))?
+        .finish()
+        .await?;
+    assert_eq!(report.loops, 2);
+    assert!(!report.aborted);
+    assert_eq!(channel.get_limit(LimitQuantity::Voltage).await?, 1.0);
+
+    channel.set_output(State::On).await?;
+    let playback = channel.play(waveform, Repeat::Forever)?;
+    playback.pause();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    playback.resume();
+    playback.abort();
+    let report = playback.finish().await?;
+    assert!(report.aborted);
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_profiles() -> Result<()> {
+    let channel = test_channel().await?;
+
+    let dip = Profile::dip(
+        5.0.into(),
+        0.5.into(),
+        0.6,
+        Duration::from_millis(100),
+        Duration::from_millis(200),
+    );
+    let report = dip.run(&channel).await?;
+    assert!(matches!(report.compiled, CompiledProfile::Host(_)));
+    assert!(report.playback.is_some());
+    assert_eq!(channel.get_limit(LimitQuantity::Voltage).await?, 5.0);
+
+    let restart = Profile::restart(
+        5.0.into(),
+        0.5.into(),
+        Duration::from_secs(1),
+        Duration::from_secs(1),
+    );
+    let report = restart.run(&channel).await?;
+    assert!(matches!(report.compiled, CompiledProfile::Timer(_)));
+    assert_eq!(report.setpoints.len(), 2);
+
+    channel.set_output(State::Off).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_iv_curve() -> Result<()> {
+    let channel = test_channel().await?;
+    let config = SweepConfig::new(0.0.into(), 3.0.into(), 7, 0.1.into());
+
+    let curve = channel.trace_iv_curve(&config).await?;
+    assert_eq!(curve.points.len(), 7);
+    assert!(curve.to_csv().lines().count() == 8);
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_find_threshold() -> Result<()> {
+    let channel = test_channel().await?;
+    let search = Search::Bisect {
+        resolution: 0.05.into(),
+    };
+    let config = ThresholdConfig::new(1.0.into(), 3.0.into(), search, 0.1.into());
+
+    // Without a DUT, the measured voltage stands in for a DUT responding.
+    let threshold = channel
+        .find_threshold(&config, async |snapshot| Ok(snapshot.voltage >= 2.0))
+        .await?;
+    assert!((threshold.voltage - 2.0).abs() <= threshold.uncertainty + 0.05);
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_endurance() -> Result<()> {
+    let spd = Arc::new(tokio::sync::Mutex::new(test_device().await?));
+    let path = std::env::temp_dir().join(format!(
+        "spd3303x-test-{}-endurance.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let mut config = EnduranceConfig::new(
+        vec![OutputChannel::One, OutputChannel::Three],
+        2,
+        Duration::from_millis(300),
+        Duration::from_millis(100),
+    );
+    config.progress = Some(path.clone());
+
+    let summary = run_endurance(spd.clone(), &config).await?;
+    assert_eq!(summary.cycles, 2);
+    assert_eq!(summary.channels.len(), 1);
+
+    config.cycles = 3;
+    let summary = run_endurance(spd, &config).await?;
+    assert_eq!((summary.cycles, summary.resumed), (3, 2));
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_emulation() -> Result<()> {
+    let channel = test_channel().await?;
+    let battery = LiIonBattery::new(1, 2.0, 0.5);
+    let expected = battery.open_circuit_voltage();
+
+    let emulator = channel.emulate(battery, EmulationConfig::default())?;
+    let mut state = emulator.subscribe();
+    state.wait_for(|state| state.updates >= 3).await.unwrap();
+    assert_eq!(emulator.get_state().errors, 0);
+    emulator.stop().await;
+
+    // Without a load, the setpoint stays at the open circuit voltage.
+    let voltage = channel.get_limit(LimitQuantity::Voltage).await?;
+    assert!((voltage - expected).abs() < 0.01);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_status_events() -> Result<()> {
+    let channel = test_channel().await?;
+    channel.set_output(State::Off).await?;
+
+    let events = StatusEvents::spawn(channel.get_device(), Duration::from_millis(100))?;
+    let mut receiver = events.subscribe();
+    // The first reading is the baseline.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    channel.set_output(State::On).await?;
+
+    let event = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match receiver.recv().await {
+                Ok(event @ StatusEvent::OutputChanged { .. }) => return Some(event),
+                Ok(_) => {}
+                Err(_) => return None,
+            }
+        }
+    })
+    .await
+    .ok()
+    .flatten();
+    assert_eq!(
+        event,
+        Some(StatusEvent::OutputChanged {
+            channel: Channel::One,
+            state: State::On,
+        })
+    );
+
+    drop(events);
+    channel.set_output(State::Off).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_enable_guarded() -> Result<()> {
+    let channel = test_channel().await?;
+
+    let guard = channel.enable_guarded().await?;
+    assert_eq!(channel.get_output().await?, State::On);
+    guard.disable().await?;
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    let guard = channel.enable_guarded().await?;
+    drop(guard);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(channel.get_output().await?, State::Off);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_operation_mode() -> Result<()> {
     let mut spd = test_device().await?;
@@ -138,3 +450,80 @@ async fn test_operation_mode() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_batch() -> Result<()> {
+    let mut spd = test_device().await?;
+
+    let (_, _, voltage, current, status) = spd
+        .execute_batch(&(
+            SetOutputStateRequest {
+                channel: OutputChannel::One,
+                state: State::Off,
+            },
+            SetLimitRequest {
+                quantity: LimitQuantity::Voltage,
+                value: 1.337.into(),
+                channel: Some(Channel::One),
+            },
+            GetLimitRequest {
+                quantity: LimitQuantity::Voltage,
+                channel: Some(Channel::One),
+            },
+            MeasureRequest {
+                quantity: Quantity::Current,
+                channel: Some(Channel::One),
+            },
+            SystemStatusRequest,
+        ))
+        .await?;
+
+    assert_eq!(f32::from(voltage.0), 1.337);
+    assert_eq!(f32::from(current.0), 0.0);
+    assert_eq!(status.decode().channel_one.output, State::Off);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_snapshot() -> Result<()> {
+    let channel = test_channel().await?;
+
+    channel
+        .set_limit(LimitQuantity::Voltage, 1.337.into())
+        .await?;
+    channel.set_output(State::On).await?;
+    let snapshot = channel.snapshot().await?;
+    channel.set_output(State::Off).await?;
+
+    assert_eq!(snapshot.channel, Channel::One);
+    assert_eq!(snapshot.voltage_limit, 1.337);
+    assert!(snapshot.voltage > 1.250);
+    assert_eq!(snapshot.output, State::On);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_measurements() -> Result<()> {
+    let channel = test_channel().await?;
+
+    let samples = channel
+        .measurements(
+            Duration::from_millis(200),
+            &[Quantity::Voltage, Quantity::Current],
+        )
+        .take(3)
+        .collect::<Vec<_>>()
+        .await;
+
+    assert_eq!(samples.len(), 3);
+    for sample in samples {
+        let sample = sample?;
+        assert!(sample.voltage.is_some());
+        assert!(sample.current.is_some());
+        assert!(sample.power.is_none());
+    }
+
+    Ok(())
+}